@@ -1,60 +1,9 @@
-use std::{cell::RefCell, rc::Rc};
+// `TransactionLog` has a file of its own so that the logs built on it in
+// other binaries can include it as well.
+#[path = "c04p01_singly_linked_list/transaction_log.rs"]
+mod transaction_log;
 
-type SingleLink = Option<Rc<RefCell<Node>>>;
-
-#[derive(Clone)]
-struct Node {
-    value: String,
-    next: SingleLink,
-}
-
-impl Node {
-    // create a new node
-    fn new(value: String) -> Rc<RefCell<Node>> {
-        Rc::new(RefCell::new(Node { value, next: None }))
-    }
-}
-struct TransactionLog {
-    head: SingleLink,
-    tail: SingleLink,
-    pub length: u64,
-}
-
-impl TransactionLog {
-    pub fn new_empty() -> Self {
-        Self {
-            head: None,
-            tail: None,
-            length: 0,
-        }
-    }
-
-    pub fn append(&mut self, value: String) {
-        let new = Node::new(value);
-        match self.tail.take() {
-            Some(old) => old.borrow_mut().next = Some(new.clone()),
-            None => self.head = Some(new.clone()),
-        };
-        self.length += 1;
-        self.tail = Some(new);
-    }
-
-    pub fn pop(&mut self) -> Option<String> {
-        self.head.take().map(|head| {
-            if let Some(next) = head.borrow_mut().next.take() {
-                self.head = Some(next);
-            } else {
-                self.tail.take();
-            }
-            self.length -= 1;
-            Rc::try_unwrap(head)
-                .ok()
-                .expect("Something is terribly wrong")
-                .into_inner()
-                .value
-        })
-    }
-}
+pub use transaction_log::TransactionLog;
 
 fn main() {
     println!("Hara Hara Mahadev!!! Singly Linked List");
@@ -63,31 +12,360 @@ fn main() {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::fmt::Debug;
+    use std::hint::black_box;
+    use std::time::{Duration, Instant};
+    use std::{cell::RefCell, rc::Rc};
 
-    #[test]
-    fn transaction_log_append() {
+    #[derive(Debug, Clone, PartialEq)]
+    struct Transaction {
+        id: u64,
+        amount: i64,
+    }
+
+    fn check_append<T: Clone + PartialEq + Debug>(items: [T; 3]) {
         let mut transaction_log = TransactionLog::new_empty();
         assert_eq!(transaction_log.length, 0);
-        transaction_log.append("Siva".to_owned());
-        transaction_log.append("Krishna".to_owned());
-        transaction_log.append("Shambho".to_owned());
+        for item in items.iter().cloned() {
+            transaction_log.append(item);
+        }
         assert_eq!(transaction_log.length, 3);
-        assert_eq!(transaction_log.pop(), Some("Siva".to_owned()));
-        assert_eq!(transaction_log.pop(), Some("Krishna".to_owned()));
-        assert_eq!(transaction_log.pop(), Some("Shambho".to_owned()));
+        for item in items {
+            assert_eq!(transaction_log.pop(), Some(item));
+        }
         assert_eq!(transaction_log.pop(), None);
+        assert_eq!(transaction_log.length, 0);
+    }
+
+    fn check_pop<T: Clone + PartialEq + Debug>(items: [T; 3]) {
+        let mut list: TransactionLog<T> = TransactionLog::new_empty();
+        assert_eq!(list.pop(), None);
+        list.append(items[0].clone());
+        list.append(items[1].clone());
+        assert_eq!(list.pop(), Some(items[0].clone()));
+        list.append(items[2].clone());
+        assert_eq!(list.length, 2);
+        assert_eq!(list.pop(), Some(items[1].clone()));
+        assert_eq!(list.pop(), Some(items[2].clone()));
+        assert_eq!(list.pop(), None);
+        assert_eq!(list.length, 0);
+    }
+
+    #[test]
+    fn transaction_log_append() {
+        check_append([
+            "Siva".to_owned(),
+            "Krishna".to_owned(),
+            "Shambho".to_owned(),
+        ]);
+        check_append([1u64, 2, 3]);
+        check_append([vec![0u8, 1], vec![], vec![2, 3, 4]]);
+        check_append([
+            Transaction { id: 1, amount: 100 },
+            Transaction { id: 2, amount: -40 },
+            Transaction { id: 3, amount: 7 },
+        ]);
     }
 
     #[test]
     fn transaction_log_pop() {
+        check_pop([
+            "Siva".to_owned(),
+            "Krishna".to_owned(),
+            "Shambho".to_owned(),
+        ]);
+        check_pop([1u64, 2, 3]);
+        check_pop([vec![0u8, 1], vec![], vec![2, 3, 4]]);
+        check_pop([
+            Transaction { id: 1, amount: 100 },
+            Transaction { id: 2, amount: -40 },
+            Transaction { id: 3, amount: 7 },
+        ]);
+    }
+
+    #[test]
+    fn transaction_log_iter() {
         let mut list = TransactionLog::new_empty();
-        assert_eq!(list.pop(), None);
+        assert_eq!(list.iter().next(), None);
         list.append("Siva".to_owned());
         list.append("Krishna".to_owned());
         list.append("Shambho".to_owned());
+        let mut iter = list.iter();
+        assert_eq!(iter.next(), Some(&"Siva".to_owned()));
+        assert_eq!(iter.next(), Some(&"Krishna".to_owned()));
+        assert_eq!(iter.next(), Some(&"Shambho".to_owned()));
+        assert_eq!(iter.next(), None);
+        // scanning the log leaves it untouched
+        assert_eq!(list.length, 3);
+        let lengths: Vec<usize> = (&list).into_iter().map(|s| s.len()).collect();
+        assert_eq!(lengths, vec![4, 7, 7]);
         assert_eq!(list.pop(), Some("Siva".to_owned()));
-        assert_eq!(list.pop(), Some("Krishna".to_owned()));
-        assert_eq!(list.pop(), Some("Shambho".to_owned()));
-        assert_eq!(list.pop(), None);
+    }
+
+    #[test]
+    fn transaction_log_iter_mut() {
+        let mut list = TransactionLog::new_empty();
+        list.append(1u64);
+        list.append(2);
+        list.append(3);
+        for value in list.iter_mut() {
+            *value *= 10;
+        }
+        for value in &mut list {
+            *value += 1;
+        }
+        assert_eq!(list.iter().copied().collect::<Vec<_>>(), vec![11, 21, 31]);
+        // the tail is still reachable after mutating through the iterator
+        list.append(4);
+        assert_eq!(list.iter().last(), Some(&4));
+        assert_eq!(list.length, 4);
+    }
+
+    #[test]
+    fn transaction_log_into_iter() {
+        let mut list = TransactionLog::new_empty();
+        list.append(vec![1u8]);
+        list.append(vec![2, 3]);
+        let mut iter = list.into_iter();
+        assert_eq!(iter.next(), Some(vec![1]));
+        assert_eq!(iter.next(), Some(vec![2, 3]));
+        assert_eq!(iter.next(), None);
+
+        let mut list = TransactionLog::new_empty();
+        list.append("Siva".to_owned());
+        list.append("Krishna".to_owned());
+        // dropping a partially consumed iterator frees the rest of the nodes
+        let mut iter = list.into_iter();
+        assert_eq!(iter.next(), Some("Siva".to_owned()));
+    }
+
+    fn from_slice<T: Clone>(items: &[T]) -> TransactionLog<T> {
+        let mut list = TransactionLog::new_empty();
+        for item in items {
+            list.append(item.clone());
+        }
+        list
+    }
+
+    // checks the entries along with `head`, `tail` and `length`
+    fn assert_log<T: Clone + PartialEq + Debug>(list: &TransactionLog<T>, expected: &[T]) {
+        assert_eq!(list.iter().cloned().collect::<Vec<_>>(), expected);
+        assert_eq!(list.length, expected.len() as u64);
+        assert_eq!(list.peek_front(), expected.first());
+        assert_eq!(list.peek_back(), expected.last());
+    }
+
+    #[test]
+    fn transaction_log_reverse() {
+        let mut list = from_slice(&[1u64, 2, 3, 4]);
+        list.reverse();
+        assert_log(&list, &[4, 3, 2, 1]);
+        list.append(0);
+        assert_log(&list, &[4, 3, 2, 1, 0]);
+
+        let mut list = from_slice(&["Siva".to_owned()]);
+        list.reverse();
+        assert_log(&list, &["Siva".to_owned()]);
+        let mut list: TransactionLog<u64> = TransactionLog::new_empty();
+        list.reverse();
+        assert_log(&list, &[]);
+    }
+
+    #[test]
+    fn transaction_log_split_off() {
+        let items = [1u64, 2, 3, 4, 5];
+        for at in 0..=items.len() {
+            let mut list = from_slice(&items);
+            let mut rest = list.split_off(at as u64);
+            assert_log(&list, &items[..at]);
+            assert_log(&rest, &items[at..]);
+            list.append(6);
+            rest.append(7);
+            assert_eq!(list.iter().last(), Some(&6));
+            assert_eq!(rest.iter().last(), Some(&7));
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn transaction_log_split_off_out_of_bounds() {
+        from_slice(&[1u64, 2]).split_off(3);
+    }
+
+    #[test]
+    fn transaction_log_append_list() {
+        let mut list = from_slice(&["Siva".to_owned(), "Krishna".to_owned()]);
+        list.append_list(from_slice(&["Shambho".to_owned()]));
+        assert_log(
+            &list,
+            &[
+                "Siva".to_owned(),
+                "Krishna".to_owned(),
+                "Shambho".to_owned(),
+            ],
+        );
+        list.append_list(TransactionLog::new_empty());
+        assert_eq!(list.length, 3);
+
+        let mut list = TransactionLog::new_empty();
+        list.append_list(from_slice(&[1u64, 2]));
+        assert_log(&list, &[1, 2]);
+        list.append(3);
+        assert_log(&list, &[1, 2, 3]);
+    }
+
+    #[test]
+    fn transaction_log_retain() {
+        let mut list = from_slice(&[1u64, 2, 3, 4, 5, 6]);
+        list.retain(|v| v % 2 == 0);
+        assert_log(&list, &[2, 4, 6]);
+        list.retain(|v| *v != 6);
+        assert_log(&list, &[2, 4]);
+        list.retain(|v| *v != 2);
+        assert_log(&list, &[4]);
+        list.retain(|_| false);
+        assert_log(&list, &[]);
+        list.append(7);
+        assert_log(&list, &[7]);
+    }
+
+    #[test]
+    fn transaction_log_dedup() {
+        let mut list = from_slice(&[1u64, 1, 2, 3, 3, 3, 1, 4, 4]);
+        list.dedup();
+        assert_log(&list, &[1, 2, 3, 1, 4]);
+        list.append(4);
+        list.dedup();
+        assert_log(&list, &[1, 2, 3, 1, 4]);
+
+        let mut list = from_slice(&["Siva".to_owned(), "Siva".to_owned()]);
+        list.dedup();
+        assert_log(&list, &["Siva".to_owned()]);
+    }
+
+    #[test]
+    fn transaction_log_drop_long() {
+        let mut list = TransactionLog::new_empty();
+        for i in 0..10_000_000u64 {
+            list.append(i);
+        }
+        assert_eq!(list.length, 10_000_000);
+        drop(list);
+    }
+
+    #[test]
+    fn transaction_log_across_threads() {
+        let mut list = from_slice(&[1u64, 2, 3, 4]);
+        // readers on several threads share the log (`Sync`)
+        let sums: Vec<u64> = std::thread::scope(|scope| {
+            let readers: Vec<_> = (0..4).map(|_| scope.spawn(|| list.iter().sum())).collect();
+            readers.into_iter().map(|r| r.join().unwrap()).collect()
+        });
+        assert_eq!(sums, vec![10; 4]);
+        // and a writer on another thread takes it over (`Send`)
+        list = std::thread::spawn(move || {
+            list.append(5);
+            list
+        })
+        .join()
+        .unwrap();
+        assert_log(&list, &[1, 2, 3, 4, 5]);
+    }
+
+    // The previous `Rc<RefCell<Node>>` layout, kept only as the baseline
+    // for `transaction_log_throughput`.
+    type RcLink<T> = Option<Rc<RefCell<RcNode<T>>>>;
+
+    struct RcNode<T> {
+        value: T,
+        next: RcLink<T>,
+    }
+
+    struct RcTransactionLog<T> {
+        head: RcLink<T>,
+        tail: RcLink<T>,
+        length: u64,
+    }
+
+    impl<T> RcTransactionLog<T> {
+        fn new_empty() -> Self {
+            Self {
+                head: None,
+                tail: None,
+                length: 0,
+            }
+        }
+
+        fn append(&mut self, value: T) {
+            let new = Rc::new(RefCell::new(RcNode { value, next: None }));
+            match self.tail.take() {
+                Some(old) => old.borrow_mut().next = Some(new.clone()),
+                None => self.head = Some(new.clone()),
+            };
+            self.length += 1;
+            self.tail = Some(new);
+        }
+
+        fn pop(&mut self) -> Option<T> {
+            self.head.take().map(|head| {
+                if let Some(next) = head.borrow_mut().next.take() {
+                    self.head = Some(next);
+                } else {
+                    self.tail.take();
+                }
+                self.length -= 1;
+                match Rc::try_unwrap(head) {
+                    Ok(node) => node.into_inner().value,
+                    Err(_) => panic!("Something is terribly wrong"),
+                }
+            })
+        }
+    }
+
+    fn time_rc_log(n: u64) -> Duration {
+        let start = Instant::now();
+        let mut log = RcTransactionLog::new_empty();
+        for i in 0..n {
+            log.append(black_box(i));
+        }
+        let mut sum = 0;
+        while let Some(value) = log.pop() {
+            sum += value;
+        }
+        assert_eq!(sum, n * (n - 1) / 2);
+        start.elapsed()
+    }
+
+    fn time_log(n: u64) -> Duration {
+        let start = Instant::now();
+        let mut log = TransactionLog::new_empty();
+        for i in 0..n {
+            log.append(black_box(i));
+        }
+        let mut sum = 0;
+        while let Some(value) = log.pop() {
+            sum += value;
+        }
+        assert_eq!(sum, n * (n - 1) / 2);
+        start.elapsed()
+    }
+
+    // cargo test --release --bin c04p01_singly_linked_list -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn transaction_log_throughput() {
+        const N: u64 = 5_000_000;
+        let mops = |d: Duration| (2 * N) as f64 / d.as_secs_f64() / 1e6;
+        for round in 1..=3 {
+            let rc = time_rc_log(N);
+            let owned = time_log(N);
+            println!(
+                "round {}: Rc<RefCell> {:.1} Mops/s, owned {:.1} Mops/s, {:.2}x",
+                round,
+                mops(rc),
+                mops(owned),
+                rc.as_secs_f64() / owned.as_secs_f64()
+            );
+        }
     }
 }
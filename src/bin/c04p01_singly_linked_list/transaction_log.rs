@@ -0,0 +1,286 @@
+use std::{marker::PhantomData, ptr::NonNull};
+
+// `Rc<RefCell<Node>>` cannot hand out plain references to the values it
+// holds, so the nodes are owned through raw pointers instead: the list owns
+// every node and `tail` is only a shortcut to the last one.
+type SingleLink<T> = Option<NonNull<Node<T>>>;
+
+struct Node<T> {
+    value: T,
+    next: SingleLink<T>,
+}
+
+impl<T> Node<T> {
+    // create a new node
+    fn new(value: T) -> NonNull<Node<T>> {
+        let node = Box::new(Node { value, next: None });
+        // SAFETY: `Box::into_raw` never returns a null pointer
+        unsafe { NonNull::new_unchecked(Box::into_raw(node)) }
+    }
+}
+pub struct TransactionLog<T> {
+    head: SingleLink<T>,
+    tail: SingleLink<T>,
+    pub length: u64,
+    _owns: PhantomData<Box<Node<T>>>,
+}
+
+impl<T> TransactionLog<T> {
+    pub fn new_empty() -> Self {
+        Self {
+            head: None,
+            tail: None,
+            length: 0,
+            _owns: PhantomData,
+        }
+    }
+
+    pub fn append(&mut self, value: T) {
+        let new = Node::new(value);
+        match self.tail.take() {
+            // SAFETY: `tail` points to the last node, which the list owns
+            Some(old) => unsafe { (*old.as_ptr()).next = Some(new) },
+            None => self.head = Some(new),
+        };
+        self.length += 1;
+        self.tail = Some(new);
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        self.head.map(|head| {
+            // SAFETY: `head` was created by `Node::new` and is unlinked here,
+            // so ownership goes back to the box
+            let head = unsafe { Box::from_raw(head.as_ptr()) };
+            self.head = head.next;
+            if self.head.is_none() {
+                self.tail = None;
+            }
+            self.length -= 1;
+            head.value
+        })
+    }
+
+    pub fn peek_front(&self) -> Option<&T> {
+        // SAFETY: `head` is owned by the list, which is borrowed
+        self.head.map(|head| unsafe { &(*head.as_ptr()).value })
+    }
+
+    pub fn peek_back(&self) -> Option<&T> {
+        // SAFETY: `tail` is owned by the list, which is borrowed
+        self.tail.map(|tail| unsafe { &(*tail.as_ptr()).value })
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            next: self.head,
+            _log: PhantomData,
+        }
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, T> {
+        IterMut {
+            next: self.head,
+            _log: PhantomData,
+        }
+    }
+
+    pub fn reverse(&mut self) {
+        let mut previous = None;
+        let mut current = self.head;
+        while let Some(node) = current {
+            // SAFETY: every node is owned by the list and visited only once
+            unsafe {
+                current = (*node.as_ptr()).next;
+                (*node.as_ptr()).next = previous;
+            }
+            previous = Some(node);
+        }
+        std::mem::swap(&mut self.head, &mut self.tail);
+    }
+
+    /// Splits the log in two at the given index. Returns everything from
+    /// `at` onwards, `self` keeps the first `at` entries.
+    ///
+    /// Panics if `at > length`.
+    pub fn split_off(&mut self, at: u64) -> Self {
+        assert!(
+            at <= self.length,
+            "cannot split off at {} of {}",
+            at,
+            self.length
+        );
+        if at == 0 {
+            return std::mem::replace(self, Self::new_empty());
+        }
+        let mut last = self.head.unwrap();
+        for _ in 1..at {
+            // SAFETY: there are at least `at` nodes
+            last = unsafe { (*last.as_ptr()).next.unwrap() };
+        }
+        // SAFETY: `last` is owned by the list
+        let head = unsafe { (*last.as_ptr()).next.take() };
+        let tail = if head.is_some() { self.tail } else { None };
+        let length = self.length - at;
+        self.tail = Some(last);
+        self.length = at;
+        Self {
+            head,
+            tail,
+            length,
+            _owns: PhantomData,
+        }
+    }
+
+    /// Moves every entry of `other` to the end of this log in O(1).
+    pub fn append_list(&mut self, mut other: Self) {
+        let Some(other_head) = other.head.take() else {
+            return;
+        };
+        match self.tail {
+            // SAFETY: `tail` points to the last node, which the list owns
+            Some(tail) => unsafe { (*tail.as_ptr()).next = Some(other_head) },
+            None => self.head = Some(other_head),
+        }
+        self.tail = other.tail.take();
+        self.length += other.length;
+        other.length = 0;
+    }
+
+    /// Keeps only the entries `keep` returns `true` for, in order.
+    pub fn retain(&mut self, mut keep: impl FnMut(&T) -> bool) {
+        let mut previous: SingleLink<T> = None;
+        let mut current = self.head;
+        while let Some(node) = current {
+            // SAFETY: every node is owned by the list and visited only once
+            unsafe {
+                current = (*node.as_ptr()).next;
+                if keep(&(*node.as_ptr()).value) {
+                    previous = Some(node);
+                    continue;
+                }
+                match previous {
+                    Some(previous) => (*previous.as_ptr()).next = current,
+                    None => self.head = current,
+                }
+                drop(Box::from_raw(node.as_ptr()));
+            }
+            self.length -= 1;
+        }
+        self.tail = previous;
+    }
+}
+
+impl<T: PartialEq> TransactionLog<T> {
+    /// Removes consecutive repeated entries.
+    pub fn dedup(&mut self) {
+        let Some(mut kept) = self.head else {
+            return;
+        };
+        // SAFETY: `kept` and its successor are distinct nodes owned by the list
+        unsafe {
+            while let Some(next) = (*kept.as_ptr()).next {
+                if (*next.as_ptr()).value == (*kept.as_ptr()).value {
+                    (*kept.as_ptr()).next = (*next.as_ptr()).next;
+                    drop(Box::from_raw(next.as_ptr()));
+                    self.length -= 1;
+                } else {
+                    kept = next;
+                }
+            }
+        }
+        self.tail = Some(kept);
+    }
+}
+
+impl<T> Drop for TransactionLog<T> {
+    // free the chain one node at a time so that very long logs can't
+    // overflow the stack with nested destructors
+    fn drop(&mut self) {
+        let mut current = self.head.take();
+        self.tail = None;
+        while let Some(node) = current {
+            // SAFETY: every node is owned by the list and visited only once
+            let node = unsafe { Box::from_raw(node.as_ptr()) };
+            current = node.next;
+        }
+        self.length = 0;
+    }
+}
+
+// SAFETY: the list owns its nodes exclusively, just like a `Box` chain would
+unsafe impl<T: Send> Send for TransactionLog<T> {}
+unsafe impl<T: Sync> Sync for TransactionLog<T> {}
+
+pub struct Iter<'a, T> {
+    next: SingleLink<T>,
+    _log: PhantomData<&'a T>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next.map(|node| {
+            // SAFETY: the list is borrowed for 'a, so its nodes stay alive
+            let node = unsafe { &*node.as_ptr() };
+            self.next = node.next;
+            &node.value
+        })
+    }
+}
+
+pub struct IterMut<'a, T> {
+    next: SingleLink<T>,
+    _log: PhantomData<&'a mut T>,
+}
+
+impl<'a, T> Iterator for IterMut<'a, T> {
+    type Item = &'a mut T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next.map(|node| {
+            // SAFETY: the list is mutably borrowed for 'a and every node is
+            // visited only once, so the references never alias
+            let node = unsafe { &mut *node.as_ptr() };
+            self.next = node.next;
+            &mut node.value
+        })
+    }
+}
+
+pub struct IntoIter<T>(TransactionLog<T>);
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.pop()
+    }
+}
+
+impl<T> IntoIterator for TransactionLog<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter(self)
+    }
+}
+
+impl<'a, T> IntoIterator for &'a TransactionLog<T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a mut TransactionLog<T> {
+    type Item = &'a mut T;
+    type IntoIter = IterMut<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
@@ -1,129 +1,215 @@
-use std::cell::RefCell;
-use std::rc::Rc;
-
-#[derive(Debug, Clone)]
-struct Node {
-    value: String,
-    next: Link,
-    prev: Link,
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::ptr::NonNull;
+
+// `BetterTransactionLog` has a file of its own so that other binaries can
+// include it as well.
+#[path = "c04p02_doubly_linked_list/better_transaction_log.rs"]
+mod better_transaction_log;
+
+pub use better_transaction_log::{
+    BetterTransactionLog, Cursor, CursorMut, IntoIter, ListIterator, ListIteratorMut,
+};
+use better_transaction_log::{Link, Node};
+
+/// A least recently used cache on top of `BetterTransactionLog`.
+///
+/// The list is kept in order of use, most recent at the front, and the
+/// index maps every key to its node so a hit can move it to the front
+/// without searching. Once `capacity` is reached, `put` evicts from the back.
+pub struct LruCache<K, V> {
+    list: BetterTransactionLog<(K, V)>,
+    index: HashMap<K, NonNull<Node<(K, V)>>>,
+    capacity: usize,
+    on_evict: Option<Box<dyn FnMut(K, V)>>,
 }
 
-type Link = Option<Rc<RefCell<Node>>>;
+impl<K: Hash + Eq + Clone, V> LruCache<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "an LRU cache needs room for one entry");
+        Self {
+            list: BetterTransactionLog::new_empty(),
+            index: HashMap::with_capacity(capacity),
+            capacity,
+            on_evict: None,
+        }
+    }
 
-impl Node {
-    fn new(value: String) -> Rc<RefCell<Node>> {
-        Rc::new(RefCell::new(Node {
-            value,
-            next: None,
-            prev: None,
-        }))
+    /// Calls `callback` with every entry evicted to make room.
+    pub fn on_evict(&mut self, callback: impl FnMut(K, V) + 'static) {
+        self.on_evict = Some(Box::new(callback));
     }
-}
 
-pub struct ListIterator {
-    current: Link,
-}
+    /// Looks up a key and marks it as the most recently used.
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        self.get_mut(key).map(|value| &*value)
+    }
 
-impl ListIterator {
-    fn new(start_at: Link) -> Self {
-        Self { current: start_at }
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let node = *self.index.get(key)?;
+        // SAFETY: the index only holds nodes of `list`
+        unsafe {
+            self.list.move_to_front(node);
+            Some(&mut (*node.as_ptr()).value.1)
+        }
     }
-}
 
-impl Iterator for ListIterator {
-    type Item = String;
-    fn next(&mut self) -> Option<Self::Item> {
-        let current = &self.current;
-        let mut result = None;
-        self.current = match current {
-            Some(ref current) => {
-                let current = current.borrow();
-                result = Some(current.value.clone());
-                current.next.clone()
-            }
-            None => None,
-        };
-        result
+    /// Looks up a key without changing how recently it was used.
+    pub fn peek(&self, key: &K) -> Option<&V> {
+        let node = self.index.get(key)?;
+        // SAFETY: the index only holds nodes of `list`
+        Some(unsafe { &(*node.as_ptr()).value.1 })
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.index.contains_key(key)
     }
-}
 
-impl DoubleEndedIterator for ListIterator {
-    fn next_back(&mut self) -> Option<Self::Item> {
-        let current = &self.current;
-        let mut result = None;
-        self.current = match current {
-            Some(ref current) => {
-                let current = current.borrow();
-                result = Some(current.value.clone());
-                current.prev.clone()
+    /// Inserts or updates an entry and marks it as the most recently used.
+    /// Returns the value it replaced.
+    pub fn put(&mut self, key: K, value: V) -> Option<V> {
+        if let Some(&node) = self.index.get(&key) {
+            // SAFETY: the index only holds nodes of `list`
+            unsafe {
+                self.list.move_to_front(node);
+                return Some(std::mem::replace(&mut (*node.as_ptr()).value.1, value));
+            }
+        }
+        if self.index.len() == self.capacity {
+            if let Some((key, value)) = self.list.pop_back() {
+                self.index.remove(&key);
+                if let Some(on_evict) = self.on_evict.as_mut() {
+                    on_evict(key, value);
+                }
             }
-            None => None,
-        };
-        result
+        }
+        self.list.push_front((key.clone(), value));
+        self.index.insert(key, self.list.head.unwrap());
+        None
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let node = self.index.remove(key)?;
+        // SAFETY: the index only holds nodes of `list`
+        Some(unsafe { self.list.unlink(node) }.value.1)
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Iterates from the most to the least recently used entry.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&K, &V)> {
+        self.list.iter().map(|(key, value)| (key, value))
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct BetterTransactionLog {
-    head: Link,
-    tail: Link,
-    pub length: u64,
+/// An edit history on top of `BetterTransactionLog`.
+///
+/// `current` points at the last applied operation. Undo walks it back along
+/// the `prev` links and redo forward along `next`; recording a new operation
+/// drops whatever could still have been redone. Only the newest `max_depth`
+/// operations are kept.
+pub struct UndoHistory<T> {
+    list: BetterTransactionLog<T>,
+    // `None` once everything has been undone
+    current: Link<T>,
+    applied: u64,
+    max_depth: u64,
 }
 
-impl BetterTransactionLog {
-    pub fn new_empty() -> Self {
+impl<T> UndoHistory<T> {
+    pub fn new(max_depth: u64) -> Self {
+        assert!(
+            max_depth > 0,
+            "an undo history needs room for one operation"
+        );
         Self {
-            head: None,
-            tail: None,
-            length: 0,
+            list: BetterTransactionLog::new_empty(),
+            current: None,
+            applied: 0,
+            max_depth,
         }
     }
 
-    pub fn append(&mut self, value: String) {
-        let new = Node::new(value);
-        match self.tail.take() {
-            Some(old) => {
-                old.borrow_mut().next = Some(new.clone());
-                new.borrow_mut().prev = Some(old);
-            }
-            None => self.head = Some(new.clone()),
-        };
-        self.length += 1;
-        self.tail = Some(new);
+    pub fn record(&mut self, operation: T) {
+        while self.list.tail != self.current {
+            self.list.pop_back();
+        }
+        self.list.append(operation);
+        self.current = self.list.tail;
+        self.applied += 1;
+        if self.list.length > self.max_depth {
+            self.list.pop();
+            self.applied -= 1;
+        }
     }
 
-    pub fn pop(&mut self) -> Option<String> {
-        self.head.take().map(|head| {
-            if let Some(next) = head.borrow_mut().next.take() {
-                next.borrow_mut().prev = None;
-                self.head = Some(next);
-            } else {
-                self.tail.take();
-            }
-            self.length -= 1;
-            Rc::try_unwrap(head)
-                .ok()
-                .expect("Something is terribly wrong")
-                .into_inner()
-                .value
-        })
+    /// Steps back over the last applied operation and returns it so the
+    /// caller can revert it.
+    pub fn undo(&mut self) -> Option<&T> {
+        let current = self.current?;
+        // SAFETY: `current` is a node of `list`
+        let node = unsafe { &*current.as_ptr() };
+        self.current = node.prev;
+        self.applied -= 1;
+        Some(&node.value)
     }
 
-    pub fn back_iter(self) -> ListIterator {
-        ListIterator::new(self.tail)
+    /// Steps forward over the next undone operation and returns it so the
+    /// caller can apply it again.
+    pub fn redo(&mut self) -> Option<&T> {
+        let next = match self.current {
+            // SAFETY: `current` is a node of `list`
+            Some(current) => unsafe { current.as_ref() }.next,
+            None => self.list.head,
+        }?;
+        self.current = Some(next);
+        self.applied += 1;
+        // SAFETY: `next` is a node of `list`
+        Some(unsafe { &(*next.as_ptr()).value })
     }
 
-    pub fn iter(&self) -> ListIterator {
-        ListIterator::new(self.head.clone())
+    /// The operation `undo` would return next.
+    pub fn current(&self) -> Option<&T> {
+        // SAFETY: `current` is a node of `list`
+        self.current
+            .map(|current| unsafe { &(*current.as_ptr()).value })
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.undo_depth() > 0
     }
-}
 
-impl IntoIterator for BetterTransactionLog {
-    type Item = String;
-    type IntoIter = ListIterator;
+    pub fn can_redo(&self) -> bool {
+        self.redo_depth() > 0
+    }
+
+    pub fn undo_depth(&self) -> u64 {
+        self.applied
+    }
 
-    fn into_iter(self) -> Self::IntoIter {
-        ListIterator::new(self.head)
+    pub fn redo_depth(&self) -> u64 {
+        self.list.length - self.applied
+    }
+
+    pub fn clear(&mut self) {
+        self.list.clear();
+        self.current = None;
+        self.applied = 0;
+    }
+
+    /// Every recorded operation, oldest first, applied or not.
+    pub fn iter(&self) -> ListIterator<'_, T> {
+        self.list.iter()
     }
 }
 
@@ -134,6 +220,68 @@ fn main() {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::cell::Cell;
+    use std::collections::VecDeque;
+
+    // The generator for the randomised tests. The seed is printed, and a
+    // failing run is replayed by setting `TEST_SEED` to it.
+    fn seeded_rng() -> StdRng {
+        let seed = std::env::var("TEST_SEED")
+            .ok()
+            .and_then(|seed| seed.parse().ok())
+            .unwrap_or_else(rand::random);
+        println!("TEST_SEED={}", seed);
+        StdRng::seed_from_u64(seed)
+    }
+
+    thread_local! {
+        static LIVE_VALUES: Cell<usize> = const { Cell::new(0) };
+    }
+
+    // A payload that counts the values of the current test thread that
+    // haven't been dropped yet, so a node that is never freed shows up as a
+    // value that is still alive.
+    #[derive(Debug, PartialEq)]
+    struct Counted(String);
+
+    impl Counted {
+        fn new(value: &str) -> Self {
+            LIVE_VALUES.with(|live| live.set(live.get() + 1));
+            Counted(value.to_owned())
+        }
+    }
+
+    impl Clone for Counted {
+        fn clone(&self) -> Self {
+            Counted::new(&self.0)
+        }
+    }
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            LIVE_VALUES.with(|live| live.set(live.get() - 1));
+        }
+    }
+
+    impl AsRef<str> for Counted {
+        fn as_ref(&self) -> &str {
+            &self.0
+        }
+    }
+
+    fn live_values() -> usize {
+        LIVE_VALUES.with(Cell::get)
+    }
+
+    fn counted(items: &[&str]) -> BetterTransactionLog<Counted> {
+        let mut list = BetterTransactionLog::new_empty();
+        for item in items {
+            list.append(Counted::new(item));
+        }
+        list
+    }
 
     #[test]
     fn better_transaction_log_append() {
@@ -175,9 +323,593 @@ mod tests {
         assert_eq!(iter.next(), Some("Shambho".to_owned()));
         assert_eq!(iter.next(), Some("Shankara".to_owned()));
 
-        let mut iter = list.clone().back_iter();
-        assert_eq!(iter.next_back(), Some("Shankara".to_owned()));
-        assert_eq!(iter.next_back(), Some("Shambho".to_owned()));
-        assert_eq!(iter.next_back(), Some("Siva".to_owned()));
+        let mut iter = list.back_iter();
+        assert_eq!(iter.next(), Some(&"Shankara".to_owned()));
+        assert_eq!(iter.next(), Some(&"Shambho".to_owned()));
+        assert_eq!(iter.next(), Some(&"Siva".to_owned()));
+        assert_eq!(iter.next(), None);
+        assert_eq!(list.length, 3);
+    }
+
+    #[test]
+    fn better_transaction_log_double_ended_iterator() {
+        let list = from_strs(&["Siva", "Shambho", "Shankara", "Mahadev"]);
+        let mut iter = list.iter();
+        assert_eq!(iter.len(), 4);
+        assert_eq!(iter.next().map(String::as_str), Some("Siva"));
+        assert_eq!(iter.next_back().map(String::as_str), Some("Mahadev"));
+        assert_eq!(iter.len(), 2);
+        assert_eq!(iter.next_back().map(String::as_str), Some("Shankara"));
+        assert_eq!(iter.next().map(String::as_str), Some("Shambho"));
+        // both ends have met in the middle
+        assert_eq!(iter.next(), None);
+        assert_eq!(iter.next_back(), None);
+        assert_eq!(iter.len(), 0);
+
+        let mut iter = list.clone().into_iter();
+        assert_eq!(iter.len(), 4);
+        assert_eq!(iter.next_back(), Some("Mahadev".to_owned()));
+        assert_eq!(iter.next(), Some("Siva".to_owned()));
+        assert_eq!(iter.collect::<Vec<_>>(), vec!["Shambho", "Shankara"]);
+
+        let values: Vec<&String> = (&list).into_iter().rev().collect();
+        assert_eq!(values, list.back_iter().collect::<Vec<_>>());
+        let empty: BetterTransactionLog<String> = BetterTransactionLog::new_empty();
+        assert_eq!(empty.iter().len(), 0);
+        assert_eq!(empty.back_iter().next(), None);
+    }
+
+    #[test]
+    fn better_transaction_log_iter_mut() {
+        let mut list = from_strs(&["Siva", "Shambho", "Shankara"]);
+        let mut iter = list.iter_mut();
+        iter.next().unwrap().push_str(" (head)");
+        iter.next_back().unwrap().push_str(" (tail)");
+        assert_eq!(iter.len(), 1);
+        iter.next().unwrap().make_ascii_uppercase();
+        assert_eq!(iter.next_back(), None);
+        for value in &mut list {
+            value.insert(0, '>');
+        }
+        assert_list(&list, &[">Siva (head)", ">SHAMBHO", ">Shankara (tail)"]);
+    }
+
+    fn from_strs(items: &[&str]) -> BetterTransactionLog<String> {
+        let mut list = BetterTransactionLog::new_empty();
+        for item in items {
+            list.append(item.to_string());
+        }
+        list
+    }
+
+    // walks the list both ways to check every link, `tail` and `length`
+    fn assert_list<T: AsRef<str>>(list: &BetterTransactionLog<T>, expected: &[&str]) {
+        let mut forward = Vec::new();
+        let mut prev = None;
+        let mut current = list.head;
+        while let Some(node) = current {
+            let node = unsafe { node.as_ref() };
+            assert_eq!(node.prev, prev);
+            forward.push(node.value.as_ref());
+            prev = current;
+            current = node.next;
+        }
+        assert_eq!(list.tail, prev);
+        assert_eq!(forward, expected);
+        assert_eq!(list.length, expected.len() as u64);
+    }
+
+    #[test]
+    fn better_transaction_log_cursor_seek() {
+        let list = from_strs(&["Siva", "Shambho", "Shankara"]);
+        let mut cursor = list.cursor_front();
+        assert_eq!(cursor.current().map(String::as_str), Some("Siva"));
+        assert_eq!(cursor.index(), Some(0));
+        assert_eq!(cursor.peek_prev(), None);
+        cursor.move_next();
+        cursor.move_next();
+        assert_eq!(cursor.current().map(String::as_str), Some("Shankara"));
+        assert_eq!(cursor.peek_prev().map(String::as_str), Some("Shambho"));
+        assert_eq!(cursor.index(), Some(2));
+        // past the tail is the ghost, and after that the head again
+        cursor.move_next();
+        assert_eq!(cursor.current(), None);
+        assert_eq!(cursor.index(), None);
+        assert_eq!(cursor.peek_next().map(String::as_str), Some("Siva"));
+        assert_eq!(cursor.peek_prev().map(String::as_str), Some("Shankara"));
+        cursor.move_next();
+        assert_eq!(cursor.index(), Some(0));
+        cursor.move_prev();
+        assert_eq!(cursor.index(), None);
+        cursor.move_prev();
+        assert_eq!(cursor.current().map(String::as_str), Some("Shankara"));
+        assert_eq!(cursor.index(), Some(2));
+
+        let mut cursor = list.cursor_back();
+        assert_eq!(cursor.index(), Some(2));
+        cursor.move_prev();
+        assert_eq!(cursor.current().map(String::as_str), Some("Shambho"));
+        assert_eq!(cursor.index(), Some(1));
+
+        let empty: BetterTransactionLog<String> = BetterTransactionLog::new_empty();
+        let mut cursor = empty.cursor_back();
+        assert_eq!(cursor.current(), None);
+        cursor.move_next();
+        assert_eq!(cursor.current(), None);
+        assert_eq!(cursor.index(), None);
+    }
+
+    #[test]
+    fn better_transaction_log_cursor_insert() {
+        let mut list = from_strs(&["Siva", "Shankara"]);
+        let mut cursor = list.cursor_front_mut();
+        cursor.insert_after("Shambho".to_owned());
+        assert_eq!(cursor.index(), Some(0));
+        cursor.insert_before("Om".to_owned());
+        assert_eq!(cursor.index(), Some(1));
+        assert_eq!(cursor.current().map(|s| s.as_str()), Some("Siva"));
+        cursor.current().unwrap().push('!');
+        cursor.move_prev();
+        cursor.move_prev();
+        // on the ghost, after means the front and before means the back
+        cursor.insert_after("Hara".to_owned());
+        cursor.insert_before("Mahadev".to_owned());
+        assert_eq!(cursor.index(), None);
+        cursor.move_prev();
+        assert_eq!(cursor.index(), Some(5));
+        assert_list(
+            &list,
+            &["Hara", "Om", "Siva!", "Shambho", "Shankara", "Mahadev"],
+        );
+
+        let mut list = BetterTransactionLog::new_empty();
+        let mut cursor = list.cursor_front_mut();
+        cursor.insert_before("Siva".to_owned());
+        cursor.insert_after("Shambho".to_owned());
+        assert_list(&list, &["Shambho", "Siva"]);
+    }
+
+    #[test]
+    fn better_transaction_log_cursor_remove() {
+        let mut list = from_strs(&["Siva", "Shambho", "Shankara", "Mahadev"]);
+        let mut cursor = list.cursor_front_mut();
+        cursor.move_next();
+        assert_eq!(cursor.remove_current(), Some("Shambho".to_owned()));
+        assert_eq!(cursor.current().map(|s| s.as_str()), Some("Shankara"));
+        assert_eq!(cursor.index(), Some(1));
+        cursor.move_next();
+        assert_eq!(cursor.remove_current(), Some("Mahadev".to_owned()));
+        assert_eq!(cursor.index(), None);
+        assert_eq!(cursor.remove_current(), None);
+        cursor.move_next();
+        assert_eq!(cursor.remove_current(), Some("Siva".to_owned()));
+        assert_eq!(cursor.index(), Some(0));
+        assert_list(&list, &["Shankara"]);
+        let mut cursor = list.cursor_back_mut();
+        assert_eq!(cursor.remove_current(), Some("Shankara".to_owned()));
+        assert_list(&list, &[]);
+        list.append("Shiva".to_owned());
+        assert_list(&list, &["Shiva"]);
+    }
+
+    #[test]
+    fn better_transaction_log_cursor_splice() {
+        let mut list = from_strs(&["Siva", "Shankara"]);
+        let mut cursor = list.cursor_front_mut();
+        cursor.splice_after(from_strs(&["Shambho", "Hara"]));
+        assert_eq!(cursor.index(), Some(0));
+        cursor.move_next();
+        cursor.splice_before(from_strs(&["Om"]));
+        assert_eq!(cursor.index(), Some(2));
+        assert_eq!(cursor.current().map(|s| s.as_str()), Some("Shambho"));
+        cursor.splice_before(BetterTransactionLog::new_empty());
+        cursor.splice_after(BetterTransactionLog::new_empty());
+        assert_eq!(cursor.index(), Some(2));
+        cursor.move_prev();
+        cursor.move_prev();
+        cursor.move_prev();
+        cursor.splice_after(from_strs(&["Aum"]));
+        cursor.splice_before(from_strs(&["Mahadev", "Namah"]));
+        assert_eq!(cursor.index(), None);
+        assert_list(
+            &list,
+            &[
+                "Aum", "Siva", "Om", "Shambho", "Hara", "Shankara", "Mahadev", "Namah",
+            ],
+        );
+
+        let mut list = BetterTransactionLog::new_empty();
+        list.cursor_back_mut().splice_before(from_strs(&["Siva"]));
+        assert_list(&list, &["Siva"]);
+    }
+
+    #[test]
+    fn better_transaction_log_cursor_as_cursor() {
+        let mut list = from_strs(&["Siva", "Shambho"]);
+        let mut cursor = list.cursor_back_mut();
+        *cursor.peek_prev().unwrap() = "Om".to_owned();
+        let view = cursor.as_cursor();
+        assert_eq!(view.index(), Some(1));
+        assert_eq!(view.peek_prev().map(String::as_str), Some("Om"));
+        assert_eq!(
+            list.clone().into_iter().collect::<Vec<_>>(),
+            vec!["Om", "Shambho"]
+        );
+    }
+
+    #[test]
+    fn better_transaction_log_deque() {
+        let mut list = BetterTransactionLog::new_empty();
+        assert_eq!(list.peek_front(), None);
+        assert_eq!(list.peek_back(), None);
+        assert_eq!(list.pop_back(), None);
+        list.push_front("Shambho".to_owned());
+        list.append("Shankara".to_owned());
+        list.push_front("Siva".to_owned());
+        assert_eq!(list.peek_front().map(String::as_str), Some("Siva"));
+        assert_eq!(list.peek_back().map(String::as_str), Some("Shankara"));
+        assert_list(&list, &["Siva", "Shambho", "Shankara"]);
+        assert_eq!(list.pop_back(), Some("Shankara".to_owned()));
+        assert_eq!(list.pop_back(), Some("Shambho".to_owned()));
+        assert_eq!(list.pop_back(), Some("Siva".to_owned()));
+        assert_eq!(list.pop_back(), None);
+        assert_list(&list, &[]);
+
+        list.append("Siva".to_owned());
+        list.push_front("Om".to_owned());
+        list.clear();
+        assert_list(&list, &[]);
+        list.push_front("Hara".to_owned());
+        assert_list(&list, &["Hara"]);
+    }
+
+    #[test]
+    fn better_transaction_log_deque_against_vec_deque() {
+        let mut rng = seeded_rng();
+        for _ in 0..50 {
+            let mut list = BetterTransactionLog::new_empty();
+            let mut oracle = VecDeque::new();
+            for step in 0..500 {
+                match rng.gen_range(0..10) {
+                    0..=2 => {
+                        list.append(step.to_string());
+                        oracle.push_back(step.to_string());
+                    }
+                    3..=5 => {
+                        list.push_front(step.to_string());
+                        oracle.push_front(step.to_string());
+                    }
+                    6 => assert_eq!(list.pop(), oracle.pop_front()),
+                    7 => assert_eq!(list.pop_back(), oracle.pop_back()),
+                    8 => {
+                        assert_eq!(list.peek_front(), oracle.front());
+                        assert_eq!(list.peek_back(), oracle.back());
+                    }
+                    _ if rng.gen_ratio(1, 20) => {
+                        list.clear();
+                        oracle.clear();
+                    }
+                    _ => {}
+                }
+                assert_eq!(list.length, oracle.len() as u64);
+            }
+            let expected: Vec<&str> = oracle.iter().map(String::as_str).collect();
+            assert_list(&list, &expected);
+            let back: Vec<&String> = list.back_iter().collect();
+            assert_eq!(back, oracle.iter().rev().collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn better_transaction_log_drop_frees_nodes() {
+        assert_eq!(live_values(), 0);
+        let mut list = counted(&["Siva", "Shambho", "Shankara"]);
+        list.push_front(Counted::new("Om"));
+        assert_eq!(live_values(), 4);
+        drop(list);
+        assert_eq!(live_values(), 0);
+
+        let mut list = counted(&["Siva", "Shambho", "Shankara"]);
+        assert_eq!(list.pop(), Some(Counted::new("Siva")));
+        assert_eq!(list.pop_back(), Some(Counted::new("Shankara")));
+        assert_eq!(live_values(), 1);
+        list.clear();
+        assert_eq!(live_values(), 0);
+
+        let list = counted(&["Siva", "Shambho", "Shankara"]);
+        let mut iter = list.into_iter();
+        iter.next();
+        assert_eq!(live_values(), 2);
+        drop(iter);
+        assert_eq!(live_values(), 0);
+
+        let mut list = counted(&["Siva", "Shambho"]);
+        let mut cursor = list.cursor_front_mut();
+        cursor.splice_after(counted(&["Om", "Hara"]));
+        cursor.remove_current();
+        assert_eq!(live_values(), 3);
+        drop(list);
+        assert_eq!(live_values(), 0);
+
+        let mut list = BetterTransactionLog::new_empty();
+        for i in 0..1_000_000 {
+            list.append(Counted::new(&i.to_string()));
+        }
+        drop(list);
+        assert_eq!(live_values(), 0);
+    }
+
+    #[test]
+    fn better_transaction_log_clone_is_deep() {
+        let mut list = counted(&["Siva", "Shambho", "Shankara"]);
+        let mut copy = list.clone();
+        assert_eq!(live_values(), 6);
+        for value in copy.iter_mut() {
+            value.0.make_ascii_uppercase();
+        }
+        copy.pop();
+        list.append(Counted::new("Mahadev"));
+        assert_list(&list, &["Siva", "Shambho", "Shankara", "Mahadev"]);
+        assert_list(&copy, &["SHAMBHO", "SHANKARA"]);
+        drop(list);
+        assert_eq!(live_values(), 2);
+        assert_list(&copy, &["SHAMBHO", "SHANKARA"]);
+        drop(copy);
+        assert_eq!(live_values(), 0);
+    }
+
+    #[test]
+    fn lru_cache_get_put() {
+        let mut cache = LruCache::new(2);
+        assert!(cache.is_empty());
+        assert_eq!(cache.put("Siva", 1), None);
+        assert_eq!(cache.put("Shambho", 2), None);
+        assert_eq!(cache.get(&"Siva"), Some(&1));
+        // "Shambho" is now the least recently used
+        assert_eq!(cache.put("Shankara", 3), None);
+        assert_eq!(cache.get(&"Shambho"), None);
+        assert!(!cache.contains_key(&"Shambho"));
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.put("Siva", 10), Some(1));
+        assert_eq!(cache.put("Mahadev", 4), None);
+        assert_eq!(cache.get(&"Shankara"), None);
+        *cache.get_mut(&"Siva").unwrap() += 1;
+        assert_eq!(
+            cache.iter().collect::<Vec<_>>(),
+            vec![(&"Siva", &11), (&"Mahadev", &4)]
+        );
+        assert_eq!(cache.capacity(), 2);
+    }
+
+    #[test]
+    fn lru_cache_peek_does_not_promote() {
+        let mut cache = LruCache::new(2);
+        cache.put(1, "Siva".to_owned());
+        cache.put(2, "Shambho".to_owned());
+        assert_eq!(cache.peek(&1).map(String::as_str), Some("Siva"));
+        cache.put(3, "Shankara".to_owned());
+        assert_eq!(cache.peek(&1), None);
+        assert_eq!(cache.peek(&2).map(String::as_str), Some("Shambho"));
+        assert_eq!(cache.remove(&2), Some("Shambho".to_owned()));
+        assert_eq!(cache.remove(&2), None);
+        assert_eq!(cache.len(), 1);
+        cache.put(4, "Mahadev".to_owned());
+        cache.put(5, "Hara".to_owned());
+        assert_eq!(
+            cache.iter().map(|(k, _)| *k).collect::<Vec<_>>(),
+            vec![5, 4]
+        );
+    }
+
+    #[test]
+    fn lru_cache_eviction_callback() {
+        let evicted = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let mut cache = LruCache::new(3);
+        {
+            let evicted = evicted.clone();
+            cache.on_evict(move |key, value| evicted.borrow_mut().push((key, value)));
+        }
+        for i in 0..6u64 {
+            cache.put(i, i * 10);
+            if i == 3 {
+                cache.get(&1);
+            }
+        }
+        assert_eq!(*evicted.borrow(), vec![(0, 0), (2, 20), (3, 30)]);
+        // replacing a value is not an eviction
+        cache.put(5, 0);
+        assert_eq!(evicted.borrow().len(), 3);
+        assert_eq!(
+            cache.iter().rev().map(|(k, _)| *k).collect::<Vec<_>>(),
+            vec![1, 4, 5]
+        );
+    }
+
+    #[test]
+    fn lru_cache_against_model() {
+        let mut rng = seeded_rng();
+        let mut cache = LruCache::new(8);
+        // most recently used first
+        let mut model: VecDeque<(u8, u32)> = VecDeque::new();
+        for step in 0..5_000u32 {
+            let key = rng.gen_range(0..16u8);
+            let found = model.iter().position(|(k, _)| *k == key);
+            if rng.gen_bool(0.5) {
+                let old = found.map(|i| model.remove(i).unwrap().1);
+                if old.is_none() && model.len() == 8 {
+                    model.pop_back();
+                }
+                model.push_front((key, step));
+                assert_eq!(cache.put(key, step), old);
+            } else {
+                let value = found.map(|i| model.remove(i).unwrap());
+                if let Some(entry) = value {
+                    model.push_front(entry);
+                }
+                assert_eq!(cache.get(&key).copied(), value.map(|(_, v)| v));
+            }
+            assert_eq!(cache.len(), model.len());
+        }
+        let entries: Vec<(u8, u32)> = cache.iter().map(|(k, v)| (*k, *v)).collect();
+        assert_eq!(entries, Vec::from(model));
+    }
+
+    #[test]
+    fn undo_history_undo_redo() {
+        let mut history = UndoHistory::new(10);
+        assert_eq!(history.undo(), None);
+        assert_eq!(history.redo(), None);
+        history.record("type Siva");
+        history.record("type Shambho");
+        history.record("bold");
+        assert_eq!(history.current(), Some(&"bold"));
+        assert_eq!(history.undo(), Some(&"bold"));
+        assert_eq!(history.undo(), Some(&"type Shambho"));
+        assert_eq!((history.undo_depth(), history.redo_depth()), (1, 2));
+        assert_eq!(history.redo(), Some(&"type Shambho"));
+        assert_eq!(history.undo(), Some(&"type Shambho"));
+        assert_eq!(history.undo(), Some(&"type Siva"));
+        assert_eq!(history.undo(), None);
+        assert!(!history.can_undo());
+        assert_eq!(history.current(), None);
+        assert_eq!(history.redo(), Some(&"type Siva"));
+        assert_eq!(history.redo(), Some(&"type Shambho"));
+        assert_eq!(history.redo(), Some(&"bold"));
+        assert_eq!(history.redo(), None);
+        assert!(!history.can_redo());
+    }
+
+    #[test]
+    fn undo_history_record_truncates_redo() {
+        let mut history = UndoHistory::new(10);
+        history.record(1);
+        history.record(2);
+        history.record(3);
+        history.undo();
+        history.undo();
+        history.record(4);
+        assert!(!history.can_redo());
+        assert_eq!(history.iter().copied().collect::<Vec<_>>(), vec![1, 4]);
+        assert_eq!(history.undo(), Some(&4));
+        assert_eq!(history.undo(), Some(&1));
+
+        // recording with everything undone starts over
+        history.record(5);
+        assert_eq!(history.iter().copied().collect::<Vec<_>>(), vec![5]);
+        assert_eq!((history.undo_depth(), history.redo_depth()), (1, 0));
+        history.clear();
+        assert_eq!(history.undo(), None);
+        history.record(6);
+        assert_eq!(history.undo(), Some(&6));
+    }
+
+    #[test]
+    fn undo_history_max_depth() {
+        let mut history = UndoHistory::new(3);
+        for i in 0..10 {
+            history.record(Counted::new(&i.to_string()));
+        }
+        assert_eq!(history.undo_depth(), 3);
+        assert_eq!(history.undo().map(AsRef::as_ref), Some("9"));
+        assert_eq!(history.undo().map(AsRef::as_ref), Some("8"));
+        assert_eq!(history.undo().map(AsRef::as_ref), Some("7"));
+        assert_eq!(history.undo(), None);
+        assert_eq!(history.redo_depth(), 3);
+        history.redo();
+        history.record(Counted::new("10"));
+        history.record(Counted::new("11"));
+        history.record(Counted::new("12"));
+        assert_eq!(
+            history.iter().map(AsRef::as_ref).collect::<Vec<_>>(),
+            vec!["10", "11", "12"]
+        );
+        assert_eq!(history.undo_depth(), 3);
+        drop(history);
+        assert_eq!(live_values(), 0);
+    }
+
+    #[test]
+    fn better_transaction_log_positional() {
+        let mut list = from_strs(&["Siva", "Shankara"]);
+        list.insert(1, "Shambho".to_owned());
+        list.insert(0, "Om".to_owned());
+        list.insert(4, "Mahadev".to_owned());
+        assert_list(&list, &["Om", "Siva", "Shambho", "Shankara", "Mahadev"]);
+        assert_eq!(list.get(0).map(String::as_str), Some("Om"));
+        assert_eq!(list.get(3).map(String::as_str), Some("Shankara"));
+        assert_eq!(list.get(5), None);
+        list.get_mut(4).unwrap().push('!');
+        assert!(list.contains(&"Mahadev!".to_owned()));
+        assert!(!list.contains(&"Mahadev".to_owned()));
+        assert_eq!(list.remove(0), "Om");
+        assert_eq!(list.remove(3), "Mahadev!");
+        assert_list(&list, &["Siva", "Shambho", "Shankara"]);
+
+        let mut rest = list.split_off(1);
+        assert_list(&list, &["Siva"]);
+        assert_list(&rest, &["Shambho", "Shankara"]);
+        assert_list(&rest.split_off(2), &[]);
+        assert_list(&rest.split_off(0), &["Shambho", "Shankara"]);
+        assert_list(&rest, &[]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn better_transaction_log_insert_out_of_bounds() {
+        from_strs(&["Siva"]).insert(2, "Shambho".to_owned());
+    }
+
+    #[test]
+    #[should_panic]
+    fn better_transaction_log_remove_out_of_bounds() {
+        from_strs(&["Siva"]).remove(1);
+    }
+
+    #[test]
+    fn better_transaction_log_positional_against_vec() {
+        let mut rng = seeded_rng();
+        for _ in 0..50 {
+            let mut list = BetterTransactionLog::new_empty();
+            let mut oracle: Vec<String> = Vec::new();
+            for step in 0..300 {
+                let len = oracle.len() as u64;
+                match rng.gen_range(0..8) {
+                    0..=2 => {
+                        let at = rng.gen_range(0..=len);
+                        list.insert(at, step.to_string());
+                        oracle.insert(at as usize, step.to_string());
+                    }
+                    3 | 4 => {
+                        if let Some(at) = (len > 0).then(|| rng.gen_range(0..len)) {
+                            assert_eq!(list.remove(at), oracle.remove(at as usize));
+                        }
+                    }
+                    5 => {
+                        let at = rng.gen_range(0..=len + 1);
+                        assert_eq!(list.get(at), oracle.get(at as usize));
+                        let value = rng.gen_range(0..=step).to_string();
+                        assert_eq!(list.contains(&value), oracle.contains(&value));
+                    }
+                    6 => {
+                        let at = rng.gen_range(0..=len);
+                        let rest = list.split_off(at);
+                        let oracle_rest = oracle.split_off(at as usize);
+                        let expected: Vec<&str> = oracle_rest.iter().map(String::as_str).collect();
+                        assert_list(&rest, &expected);
+                        // put it back so the list keeps growing
+                        list.cursor_back_mut().splice_after(rest);
+                        oracle.extend(oracle_rest);
+                    }
+                    _ => {
+                        if let Some(at) = (len > 0).then(|| rng.gen_range(0..len)) {
+                            list.get_mut(at).unwrap().push('*');
+                            oracle[at as usize].push('*');
+                        }
+                    }
+                }
+            }
+            let expected: Vec<&str> = oracle.iter().map(String::as_str).collect();
+            assert_list(&list, &expected);
+        }
     }
 }
@@ -0,0 +1,699 @@
+use std::fmt;
+use std::iter::Rev;
+use std::marker::PhantomData;
+use std::ptr::NonNull;
+
+// A cursor has to hand out plain references to the values, which
+// `Rc<RefCell<Node>>` can't do, so the list owns its nodes through raw
+// pointers: `next` and `prev` are only ever followed while the list is alive.
+pub(super) struct Node<T> {
+    pub(super) value: T,
+    pub(super) next: Link<T>,
+    // `prev` never owns anything, so there are no cycles to leak
+    pub(super) prev: Link<T>,
+}
+
+pub(super) type Link<T> = Option<NonNull<Node<T>>>;
+
+impl<T> Node<T> {
+    fn new(value: T) -> NonNull<Node<T>> {
+        let node = Box::new(Node {
+            value,
+            next: None,
+            prev: None,
+        });
+        // SAFETY: `Box::into_raw` never returns a null pointer
+        unsafe { NonNull::new_unchecked(Box::into_raw(node)) }
+    }
+}
+
+/// Borrowing iterator over a `BetterTransactionLog`. It walks in from both
+/// ends and stops once they meet.
+pub struct ListIterator<'a, T> {
+    head: Link<T>,
+    tail: Link<T>,
+    length: u64,
+    _list: PhantomData<&'a Node<T>>,
+}
+
+impl<'a, T> Iterator for ListIterator<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.length == 0 {
+            return None;
+        }
+        self.head.map(|head| {
+            // SAFETY: the list is borrowed for 'a and `length` keeps the
+            // iterator from walking past the other end
+            let head = unsafe { &*head.as_ptr() };
+            self.length -= 1;
+            self.head = head.next;
+            &head.value
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.length as usize, Some(self.length as usize))
+    }
+}
+
+impl<T> DoubleEndedIterator for ListIterator<'_, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.length == 0 {
+            return None;
+        }
+        self.tail.map(|tail| {
+            // SAFETY: as in `next`
+            let tail = unsafe { &*tail.as_ptr() };
+            self.length -= 1;
+            self.tail = tail.prev;
+            &tail.value
+        })
+    }
+}
+
+impl<T> ExactSizeIterator for ListIterator<'_, T> {}
+
+/// Mutably borrowing iterator over a `BetterTransactionLog`.
+pub struct ListIteratorMut<'a, T> {
+    head: Link<T>,
+    tail: Link<T>,
+    length: u64,
+    _list: PhantomData<&'a mut Node<T>>,
+}
+
+impl<'a, T> Iterator for ListIteratorMut<'a, T> {
+    type Item = &'a mut T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.length == 0 {
+            return None;
+        }
+        self.head.map(|head| {
+            // SAFETY: the list is mutably borrowed for 'a and `length` makes
+            // sure no node is handed out twice
+            let head = unsafe { &mut *head.as_ptr() };
+            self.length -= 1;
+            self.head = head.next;
+            &mut head.value
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.length as usize, Some(self.length as usize))
+    }
+}
+
+impl<T> DoubleEndedIterator for ListIteratorMut<'_, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.length == 0 {
+            return None;
+        }
+        self.tail.map(|tail| {
+            // SAFETY: as in `next`
+            let tail = unsafe { &mut *tail.as_ptr() };
+            self.length -= 1;
+            self.tail = tail.prev;
+            &mut tail.value
+        })
+    }
+}
+
+impl<T> ExactSizeIterator for ListIteratorMut<'_, T> {}
+
+pub struct IntoIter<T> {
+    list: BetterTransactionLog<T>,
+}
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.list.pop()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.list.length as usize, Some(self.list.length as usize))
+    }
+}
+
+impl<T> DoubleEndedIterator for IntoIter<T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.list.pop_back()
+    }
+}
+
+impl<T> ExactSizeIterator for IntoIter<T> {}
+
+pub struct BetterTransactionLog<T> {
+    pub(super) head: Link<T>,
+    pub(super) tail: Link<T>,
+    pub length: u64,
+    _owns: PhantomData<Box<Node<T>>>,
+}
+
+impl<T> BetterTransactionLog<T> {
+    pub fn new_empty() -> Self {
+        Self {
+            head: None,
+            tail: None,
+            length: 0,
+            _owns: PhantomData,
+        }
+    }
+
+    pub fn append(&mut self, value: T) {
+        // SAFETY: a new node goes after the current tail
+        unsafe { self.link(Node::new(value), self.tail, None) }
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        self.head.map(|head| {
+            // SAFETY: `head` is owned by the list
+            unsafe { self.unlink(head) }.value
+        })
+    }
+
+    pub fn push_front(&mut self, value: T) {
+        // SAFETY: a new node goes before the current head
+        unsafe { self.link(Node::new(value), None, self.head) }
+    }
+
+    pub fn pop_back(&mut self) -> Option<T> {
+        self.tail.map(|tail| {
+            // SAFETY: `tail` is owned by the list
+            unsafe { self.unlink(tail) }.value
+        })
+    }
+
+    pub fn peek_front(&self) -> Option<&T> {
+        // SAFETY: `head` is owned by the list, which is borrowed
+        self.head.map(|head| unsafe { &(*head.as_ptr()).value })
+    }
+
+    pub fn peek_back(&self) -> Option<&T> {
+        // SAFETY: `tail` is owned by the list, which is borrowed
+        self.tail.map(|tail| unsafe { &(*tail.as_ptr()).value })
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::new_empty();
+    }
+
+    pub fn get(&self, index: u64) -> Option<&T> {
+        // SAFETY: the node is owned by the list, which is borrowed
+        self.node_at(index)
+            .map(|node| unsafe { &(*node.as_ptr()).value })
+    }
+
+    pub fn get_mut(&mut self, index: u64) -> Option<&mut T> {
+        // SAFETY: the node is owned by the list, which is mutably borrowed
+        self.node_at(index)
+            .map(|node| unsafe { &mut (*node.as_ptr()).value })
+    }
+
+    /// Inserts `value` so that it ends up at `index`, shifting everything
+    /// after it towards the back.
+    ///
+    /// Panics if `index > length`.
+    pub fn insert(&mut self, index: u64, value: T) {
+        assert!(
+            index <= self.length,
+            "cannot insert at {} of {}",
+            index,
+            self.length
+        );
+        match self.node_at(index) {
+            // SAFETY: `node` and its `prev` are adjacent nodes of the list
+            Some(node) => unsafe { self.link(Node::new(value), (*node.as_ptr()).prev, Some(node)) },
+            None => self.append(value),
+        }
+    }
+
+    /// Removes and returns the entry at `index`, shifting everything after
+    /// it towards the front.
+    ///
+    /// Panics if `index >= length`, like `insert` and `Vec::remove`.
+    pub fn remove(&mut self, index: u64) -> T {
+        let Some(node) = self.node_at(index) else {
+            panic!("cannot remove at {} of {}", index, self.length);
+        };
+        // SAFETY: `node` is owned by the list
+        unsafe { self.unlink(node) }.value
+    }
+
+    /// Splits the list in two at the given index. Returns everything from
+    /// `at` onwards, `self` keeps the first `at` entries.
+    ///
+    /// Panics if `at > length`.
+    pub fn split_off(&mut self, at: u64) -> Self {
+        assert!(
+            at <= self.length,
+            "cannot split off at {} of {}",
+            at,
+            self.length
+        );
+        let Some(first) = self.node_at(at) else {
+            return Self::new_empty();
+        };
+        // SAFETY: `first` is owned by the list and so is its `prev`
+        let last = unsafe {
+            let last = (*first.as_ptr()).prev.take();
+            match last {
+                Some(last) => (*last.as_ptr()).next = None,
+                None => self.head = None,
+            }
+            last
+        };
+        let rest = Self {
+            head: Some(first),
+            tail: self.tail,
+            length: self.length - at,
+            _owns: PhantomData,
+        };
+        self.tail = last;
+        self.length = at;
+        rest
+    }
+
+    // Finds the node at `index`, walking in from whichever end is closer.
+    fn node_at(&self, index: u64) -> Link<T> {
+        if index >= self.length {
+            return None;
+        }
+        let mut node;
+        if index < self.length / 2 {
+            node = self.head?;
+            for _ in 0..index {
+                // SAFETY: there are more than `index` nodes after the head
+                node = unsafe { node.as_ref() }.next?;
+            }
+        } else {
+            node = self.tail?;
+            for _ in index + 1..self.length {
+                // SAFETY: there are at least `length - index` nodes before
+                // the tail
+                node = unsafe { node.as_ref() }.prev?;
+            }
+        }
+        Some(node)
+    }
+
+    /// Iterates from the tail to the head.
+    pub fn back_iter(&self) -> Rev<ListIterator<'_, T>> {
+        self.iter().rev()
+    }
+
+    pub fn iter(&self) -> ListIterator<'_, T> {
+        ListIterator {
+            head: self.head,
+            tail: self.tail,
+            length: self.length,
+            _list: PhantomData,
+        }
+    }
+
+    pub fn iter_mut(&mut self) -> ListIteratorMut<'_, T> {
+        ListIteratorMut {
+            head: self.head,
+            tail: self.tail,
+            length: self.length,
+            _list: PhantomData,
+        }
+    }
+
+    pub fn cursor_front(&self) -> Cursor<'_, T> {
+        Cursor {
+            current: self.head,
+            index: 0,
+            list: self,
+        }
+    }
+
+    pub fn cursor_back(&self) -> Cursor<'_, T> {
+        Cursor {
+            current: self.tail,
+            index: self.length.saturating_sub(1),
+            list: self,
+        }
+    }
+
+    pub fn cursor_front_mut(&mut self) -> CursorMut<'_, T> {
+        CursorMut {
+            current: self.head,
+            index: 0,
+            list: self,
+        }
+    }
+
+    pub fn cursor_back_mut(&mut self) -> CursorMut<'_, T> {
+        CursorMut {
+            current: self.tail,
+            index: self.length.saturating_sub(1),
+            list: self,
+        }
+    }
+
+    // Links a new node in between `prev` and `next`, which have to be
+    // adjacent nodes of this list (`None` standing for either end).
+    unsafe fn link(&mut self, node: NonNull<Node<T>>, prev: Link<T>, next: Link<T>) {
+        (*node.as_ptr()).prev = prev;
+        (*node.as_ptr()).next = next;
+        match prev {
+            Some(prev) => (*prev.as_ptr()).next = Some(node),
+            None => self.head = Some(node),
+        }
+        match next {
+            Some(next) => (*next.as_ptr()).prev = Some(node),
+            None => self.tail = Some(node),
+        }
+        self.length += 1;
+    }
+
+    // Takes a node of this list out of it and hands back its ownership.
+    pub(super) unsafe fn unlink(&mut self, node: NonNull<Node<T>>) -> Box<Node<T>> {
+        self.detach(node);
+        Box::from_raw(node.as_ptr())
+    }
+
+    // Takes a node of this list out of it without freeing it.
+    unsafe fn detach(&mut self, node: NonNull<Node<T>>) {
+        let (prev, next) = ((*node.as_ptr()).prev, (*node.as_ptr()).next);
+        match prev {
+            Some(prev) => (*prev.as_ptr()).next = next,
+            None => self.head = next,
+        }
+        match next {
+            Some(next) => (*next.as_ptr()).prev = prev,
+            None => self.tail = prev,
+        }
+        self.length -= 1;
+    }
+
+    // Moves a node of this list to the front in O(1).
+    pub(super) unsafe fn move_to_front(&mut self, node: NonNull<Node<T>>) {
+        if self.head != Some(node) {
+            self.detach(node);
+            self.link(node, None, self.head);
+        }
+    }
+
+    // Moves every node of `other` in between the adjacent `prev` and `next`.
+    unsafe fn splice(&mut self, prev: Link<T>, next: Link<T>, mut other: BetterTransactionLog<T>) {
+        let (Some(first), Some(last)) = (other.head.take(), other.tail.take()) else {
+            return;
+        };
+        (*first.as_ptr()).prev = prev;
+        (*last.as_ptr()).next = next;
+        match prev {
+            Some(prev) => (*prev.as_ptr()).next = Some(first),
+            None => self.head = Some(first),
+        }
+        match next {
+            Some(next) => (*next.as_ptr()).prev = Some(last),
+            None => self.tail = Some(last),
+        }
+        self.length += other.length;
+        other.length = 0;
+    }
+}
+
+impl<T: PartialEq> BetterTransactionLog<T> {
+    pub fn contains(&self, value: &T) -> bool {
+        self.iter().any(|v| v == value)
+    }
+}
+
+impl<T> Drop for BetterTransactionLog<T> {
+    fn drop(&mut self) {
+        let mut current = self.head.take();
+        self.tail = None;
+        while let Some(node) = current {
+            // SAFETY: every node is owned by the list and visited only once
+            let node = unsafe { Box::from_raw(node.as_ptr()) };
+            current = node.next;
+        }
+        self.length = 0;
+    }
+}
+
+impl<T: Clone> Clone for BetterTransactionLog<T> {
+    fn clone(&self) -> Self {
+        let mut list = Self::new_empty();
+        for value in self.iter() {
+            list.append(value.clone());
+        }
+        list
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for BetterTransactionLog<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<T> IntoIterator for BetterTransactionLog<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter { list: self }
+    }
+}
+
+impl<'a, T> IntoIterator for &'a BetterTransactionLog<T> {
+    type Item = &'a T;
+    type IntoIter = ListIterator<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a mut BetterTransactionLog<T> {
+    type Item = &'a mut T;
+    type IntoIter = ListIteratorMut<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+/// A read-only position in a `BetterTransactionLog`, modelled on
+/// `std::collections::linked_list::Cursor`.
+///
+/// Besides pointing at an entry, a cursor can sit on the "ghost" position
+/// between the tail and the head. Moving past either end lands on the ghost,
+/// and moving on from the ghost wraps around to the other end.
+pub struct Cursor<'a, T> {
+    current: Link<T>,
+    index: u64,
+    list: &'a BetterTransactionLog<T>,
+}
+
+impl<'a, T> Cursor<'a, T> {
+    /// The position of the current entry, `None` on the ghost.
+    pub fn index(&self) -> Option<u64> {
+        self.current.map(|_| self.index)
+    }
+
+    pub fn move_next(&mut self) {
+        match self.current {
+            // SAFETY: `current` is owned by the borrowed list
+            Some(current) => {
+                self.current = unsafe { current.as_ref() }.next;
+                self.index += 1;
+            }
+            None => {
+                self.current = self.list.head;
+                self.index = 0;
+            }
+        }
+    }
+
+    pub fn move_prev(&mut self) {
+        match self.current {
+            // SAFETY: `current` is owned by the borrowed list
+            Some(current) => {
+                self.current = unsafe { current.as_ref() }.prev;
+                self.index = self.index.checked_sub(1).unwrap_or(self.list.length);
+            }
+            None => {
+                self.current = self.list.tail;
+                self.index = self.list.length.saturating_sub(1);
+            }
+        }
+    }
+
+    pub fn current(&self) -> Option<&'a T> {
+        // SAFETY: `current` is owned by the list, which is borrowed for 'a
+        self.current
+            .map(|current| unsafe { &(*current.as_ptr()).value })
+    }
+
+    pub fn peek_next(&self) -> Option<&'a T> {
+        let next = match self.current {
+            // SAFETY: `current` is owned by the borrowed list
+            Some(current) => unsafe { current.as_ref() }.next,
+            None => self.list.head,
+        };
+        // SAFETY: as in `current`
+        next.map(|next| unsafe { &(*next.as_ptr()).value })
+    }
+
+    pub fn peek_prev(&self) -> Option<&'a T> {
+        let prev = match self.current {
+            // SAFETY: `current` is owned by the borrowed list
+            Some(current) => unsafe { current.as_ref() }.prev,
+            None => self.list.tail,
+        };
+        // SAFETY: as in `current`
+        prev.map(|prev| unsafe { &(*prev.as_ptr()).value })
+    }
+}
+
+/// A position in a `BetterTransactionLog` that can edit the list around it,
+/// modelled on `std::collections::linked_list::CursorMut`. Every edit is O(1).
+pub struct CursorMut<'a, T> {
+    current: Link<T>,
+    index: u64,
+    list: &'a mut BetterTransactionLog<T>,
+}
+
+impl<T> CursorMut<'_, T> {
+    /// The position of the current entry, `None` on the ghost.
+    pub fn index(&self) -> Option<u64> {
+        self.current.map(|_| self.index)
+    }
+
+    pub fn move_next(&mut self) {
+        match self.current {
+            // SAFETY: `current` is owned by the borrowed list
+            Some(current) => {
+                self.current = unsafe { current.as_ref() }.next;
+                self.index += 1;
+            }
+            None => {
+                self.current = self.list.head;
+                self.index = 0;
+            }
+        }
+    }
+
+    pub fn move_prev(&mut self) {
+        match self.current {
+            // SAFETY: `current` is owned by the borrowed list
+            Some(current) => {
+                self.current = unsafe { current.as_ref() }.prev;
+                self.index = self.index.checked_sub(1).unwrap_or(self.list.length);
+            }
+            None => {
+                self.current = self.list.tail;
+                self.index = self.list.length.saturating_sub(1);
+            }
+        }
+    }
+
+    pub fn current(&mut self) -> Option<&mut T> {
+        // SAFETY: `current` is owned by the list, which the cursor borrows
+        // mutably, and the reference is tied to the cursor
+        self.current
+            .map(|current| unsafe { &mut (*current.as_ptr()).value })
+    }
+
+    pub fn peek_next(&mut self) -> Option<&mut T> {
+        let next = match self.current {
+            // SAFETY: `current` is owned by the borrowed list
+            Some(current) => unsafe { current.as_ref() }.next,
+            None => self.list.head,
+        };
+        // SAFETY: as in `current`
+        next.map(|next| unsafe { &mut (*next.as_ptr()).value })
+    }
+
+    pub fn peek_prev(&mut self) -> Option<&mut T> {
+        let prev = match self.current {
+            // SAFETY: `current` is owned by the borrowed list
+            Some(current) => unsafe { current.as_ref() }.prev,
+            None => self.list.tail,
+        };
+        // SAFETY: as in `current`
+        prev.map(|prev| unsafe { &mut (*prev.as_ptr()).value })
+    }
+
+    /// A read-only cursor at the same position.
+    pub fn as_cursor(&self) -> Cursor<'_, T> {
+        Cursor {
+            current: self.current,
+            index: self.index,
+            list: self.list,
+        }
+    }
+
+    /// Inserts after the current entry, or at the front on the ghost.
+    pub fn insert_after(&mut self, value: T) {
+        let next = self.next_link();
+        // SAFETY: `current` and `next` are adjacent nodes of the list
+        unsafe { self.list.link(Node::new(value), self.current, next) };
+        if self.current.is_none() {
+            self.index += 1;
+        }
+    }
+
+    /// Inserts before the current entry, or at the back on the ghost.
+    pub fn insert_before(&mut self, value: T) {
+        let prev = self.prev_link();
+        // SAFETY: `prev` and `current` are adjacent nodes of the list
+        unsafe { self.list.link(Node::new(value), prev, self.current) };
+        self.index += 1;
+    }
+
+    /// Removes the current entry and moves on to the next one. Does nothing
+    /// on the ghost.
+    pub fn remove_current(&mut self) -> Option<T> {
+        let current = self.current?;
+        // SAFETY: `current` is owned by the list
+        let node = unsafe { self.list.unlink(current) };
+        self.current = node.next;
+        Some(node.value)
+    }
+
+    /// Moves every entry of `other` after the current entry, or to the front
+    /// on the ghost.
+    pub fn splice_after(&mut self, other: BetterTransactionLog<T>) {
+        let added = other.length;
+        let next = self.next_link();
+        // SAFETY: `current` and `next` are adjacent nodes of the list
+        unsafe { self.list.splice(self.current, next, other) };
+        if self.current.is_none() {
+            self.index += added;
+        }
+    }
+
+    /// Moves every entry of `other` before the current entry, or to the back
+    /// on the ghost.
+    pub fn splice_before(&mut self, other: BetterTransactionLog<T>) {
+        let added = other.length;
+        let prev = self.prev_link();
+        // SAFETY: `prev` and `current` are adjacent nodes of the list
+        unsafe { self.list.splice(prev, self.current, other) };
+        self.index += added;
+    }
+
+    fn next_link(&self) -> Link<T> {
+        match self.current {
+            // SAFETY: `current` is owned by the borrowed list
+            Some(current) => unsafe { current.as_ref() }.next,
+            None => self.list.head,
+        }
+    }
+
+    fn prev_link(&self) -> Link<T> {
+        match self.current {
+            // SAFETY: `current` is owned by the borrowed list
+            Some(current) => unsafe { current.as_ref() }.prev,
+            None => self.list.tail,
+        }
+    }
+}
@@ -1,45 +1,261 @@
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Bound, RangeBounds};
 
-// Revisit this to get more clarity
-
-type Link = Option<Rc<RefCell<Node>>>;
+// Nodes of a `SkipMap` live in an arena and link to each other by slot.
+type Slot = Option<usize>;
 
 #[derive(Clone)]
-struct Node {
-    next: Vec<Link>,
-    pub offset: u64,
-    pub command: String,
-}
-
-impl Node {
-    fn new(links: Vec<Link>, offset: u64, command: String) -> Rc<RefCell<Node>> {
-        Rc::new(RefCell::new(Node {
-            next: links,
-            offset,
-            command,
-        }))
-    }
+struct SkipNode<K, V> {
+    key: K,
+    value: V,
+    next: Vec<Slot>,
+    // how many bottom level steps each link in `next` jumps; a link off the
+    // end counts the nodes left after this one
+    span: Vec<usize>,
+    // only the bottom level links backwards, for reverse iteration
+    prev: Slot,
 }
 
+/// An ordered map on a skip list, with the same express lanes as
+/// `BestTransactionLog` but any `Ord` key and value.
+///
+/// The nodes sit in a `Vec` and point at each other by index, which keeps
+/// them in one allocation and lets `get_mut` hand out plain references.
+/// Removed slots are reused by later inserts. `tails` remembers the last
+/// node on every level, so inserting past the largest key stays as cheap as
+/// the old `append`. Every link also knows how many entries it jumps, so
+/// positions can be found on the way down like keys.
 #[derive(Clone)]
-pub struct BestTransactionLog {
-    head: Link,
-    tails: Vec<Link>,
+pub struct SkipMap<K, V> {
+    nodes: Vec<Option<SkipNode<K, V>>>,
+    free: Vec<usize>,
+    head: Vec<Slot>,
+    head_span: Vec<usize>,
+    tails: Vec<Slot>,
     max_level: usize,
-    pub length: u64,
+    length: usize,
 }
 
-impl BestTransactionLog {
-    pub fn new_empty(max_level: usize) -> Self {
+// On every level, the last node some search stopped at and its rank,
+// counting the head as 0 and the first entry as 1.
+type Path = Vec<(Slot, usize)>;
+
+impl<K: Ord, V> SkipMap<K, V> {
+    pub fn new(max_level: usize) -> Self {
         Self {
-            max_level,
-            head: None,
+            nodes: Vec::new(),
+            free: Vec::new(),
+            head: vec![None; max_level + 1],
+            head_span: vec![0; max_level + 1],
             tails: vec![None; max_level + 1],
+            max_level,
             length: 0,
         }
     }
 
+    /// Inserts or replaces the value for `key`, handing back the one it
+    /// replaced.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let path = self.path_to(&key);
+        if let Some(found) = self.next_of(path[0].0, 0) {
+            let node = self.node_mut(found);
+            if node.key == key {
+                return Some(std::mem::replace(&mut node.value, value));
+            }
+        }
+        let level = self.get_level();
+        let rank = path[0].1 + 1;
+        let (next, span) = path[..=level]
+            .iter()
+            .enumerate()
+            .map(|(l, &(at, at_rank))| {
+                let jumped = rank - 1 - at_rank;
+                (self.next_of(at, l), self.span_of(at, l) - jumped)
+            })
+            .unzip();
+        let slot = self.alloc(SkipNode {
+            key,
+            value,
+            next,
+            span,
+            prev: path[0].0,
+        });
+        if let Some(next) = self.next_of(Some(slot), 0) {
+            self.node_mut(next).prev = Some(slot);
+        }
+        for (l, &(at, at_rank)) in path.iter().enumerate() {
+            if l > level {
+                *self.span_mut(at, l) += 1;
+                continue;
+            }
+            self.set_next(at, l, Some(slot));
+            *self.span_mut(at, l) = rank - at_rank;
+            if self.tails[l] == at {
+                self.tails[l] = Some(slot);
+            }
+        }
+        self.length += 1;
+        None
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.find(key).map(|slot| &self.node(slot).value)
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        self.find(key).map(|slot| &mut self.node_mut(slot).value)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.find(key).is_some()
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let path = self.path_to(key);
+        let slot = self.next_of(path[0].0, 0)?;
+        if self.node(slot).key != *key {
+            return None;
+        }
+        Some(self.unlink(&path, slot).value)
+    }
+
+    /// The entry at `index` in key order, in expected O(log n).
+    pub fn nth(&self, index: usize) -> Option<(&K, &V)> {
+        if index >= self.length {
+            return None;
+        }
+        let (at, _) = self.path_where(|_, rank| rank <= index + 1)[0];
+        at.map(|slot| self.entry(slot))
+    }
+
+    /// How many keys sort before `key`, whether it is in the map or not.
+    pub fn rank(&self, key: &K) -> usize {
+        self.path_where(|other, _| other < key)[0].1
+    }
+
+    /// Removes the entry at `index` in key order.
+    pub fn remove_at(&mut self, index: usize) -> Option<(K, V)> {
+        if index >= self.length {
+            return None;
+        }
+        let path = self.path_where(|_, rank| rank <= index);
+        let slot = self.next_of(path[0].0, 0)?;
+        let node = self.unlink(&path, slot);
+        Some((node.key, node.value))
+    }
+
+    /// Drops every entry with a key before `key`.
+    pub fn truncate_before(&mut self, key: &K) {
+        let path = self.path_to(key);
+        let dropped = path[0].1;
+        let kept: Path = path
+            .iter()
+            .enumerate()
+            .map(|(level, &(at, rank))| {
+                let first = self.next_of(at, level);
+                (first, rank + self.span_of(at, level) - dropped)
+            })
+            .collect();
+        let mut at = self.head[0];
+        while at != kept[0].0 {
+            at = self
+                .release(at.expect("the path is linked from the head"))
+                .next[0];
+        }
+        if let Some(first) = kept[0].0 {
+            self.node_mut(first).prev = None;
+        }
+        for (level, (first, span)) in kept.into_iter().enumerate() {
+            self.head[level] = first;
+            self.head_span[level] = span;
+            if first.is_none() {
+                self.tails[level] = None;
+            }
+        }
+    }
+
+    /// Drops every entry with a key after `key`.
+    pub fn truncate_after(&mut self, key: &K) {
+        let mut path = self.path_to(key);
+        for (level, (last, rank)) in path.iter_mut().enumerate() {
+            if let Some(next) = self.next_of(*last, level) {
+                if self.node(next).key == *key {
+                    *rank += self.span_of(*last, level);
+                    *last = Some(next);
+                }
+            }
+        }
+        let mut at = self.next_of(path[0].0, 0);
+        while let Some(slot) = at {
+            at = self.release(slot).next[0];
+        }
+        for (level, &(last, rank)) in path.iter().enumerate() {
+            self.set_next(last, level, None);
+            *self.span_mut(last, level) = self.length - rank;
+            self.tails[level] = last;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    pub fn first(&self) -> Option<(&K, &V)> {
+        self.head[0].map(|slot| self.entry(slot))
+    }
+
+    pub fn last(&self) -> Option<(&K, &V)> {
+        self.tails[0].map(|slot| self.entry(slot))
+    }
+
+    /// Iterates in key order.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            map: self,
+            front: self.head[0],
+            back: self.tails[0],
+            remaining: self.length,
+        }
+    }
+
+    /// Iterates over the keys in `range`, in order. Both ends are found by
+    /// descending through the express lanes rather than walking from the
+    /// head.
+    pub fn range(&self, range: impl RangeBounds<K>) -> Range<'_, K, V> {
+        let front = match range.start_bound() {
+            Bound::Included(start) => self.next_of(self.last_where(|key| key < start), 0),
+            Bound::Excluded(start) => self.next_of(self.last_where(|key| key <= start), 0),
+            Bound::Unbounded => self.head[0],
+        };
+        let back = match range.end_bound() {
+            Bound::Included(end) => self.last_where(|key| key <= end),
+            Bound::Excluded(end) => self.last_where(|key| key < end),
+            Bound::Unbounded => self.tails[0],
+        };
+        match (front, back) {
+            (Some(first), Some(last)) if self.node(first).key <= self.node(last).key => Range {
+                map: self,
+                front,
+                back,
+            },
+            _ => Range {
+                map: self,
+                front: None,
+                back: None,
+            },
+        }
+    }
+
+    /// Iterates over every key from `key` on, in order.
+    pub fn seek(&self, key: &K) -> Range<'_, K, V> {
+        self.range((Bound::Included(key), Bound::Unbounded))
+    }
+
     fn get_level(&self) -> usize {
         let mut n = 0;
         while rand::random::<bool>() && n < self.max_level {
@@ -48,128 +264,859 @@ impl BestTransactionLog {
         n
     }
 
-    pub fn append(&mut self, offset: u64, value: String) {
-        let level = 1 + if self.head.is_none() {
-            self.max_level // use the maximum level for the first node
-        } else {
-            self.get_level() // determine the level by coin flips
-        };
+    // The path to the last node on every level that still sorts before
+    // `key`, `None` standing for the head.
+    fn path_to(&self, key: &K) -> Path {
+        if let Some(last) = self.tails[0] {
+            if self.node(last).key < *key {
+                return self
+                    .tails
+                    .iter()
+                    .enumerate()
+                    .map(|(level, &tail)| (tail, self.length - self.span_of(tail, level)))
+                    .collect();
+            }
+        }
+        self.path_where(|other, _| other < key)
+    }
 
-        let new = Node::new(vec![None; level], offset, value);
+    // Descends through the express lanes past every node that passes
+    // `before`, which gets the key and rank of a node and has to hold for a
+    // prefix of them.
+    fn path_where(&self, before: impl Fn(&K, usize) -> bool) -> Path {
+        let mut path = vec![(None, 0); self.max_level + 1];
+        let (mut at, mut rank) = (None, 0);
+        for level in (0..=self.max_level).rev() {
+            while let Some(next) = self.next_of(at, level) {
+                let next_rank = rank + self.span_of(at, level);
+                if !before(&self.node(next).key, next_rank) {
+                    break;
+                }
+                (at, rank) = (Some(next), next_rank);
+            }
+            path[level] = (at, rank);
+        }
+        path
+    }
 
-        // update the tails for each level
-        for i in 0..level {
-            if let Some(old) = self.tails[i].take() {
-                let next = &mut old.borrow_mut().next;
-                next[i] = Some(new.clone());
+    // The last node whose key passes `before`, which has to hold for a
+    // prefix of the keys. `None` if not even the first one does.
+    fn last_where(&self, before: impl Fn(&K) -> bool) -> Slot {
+        self.path_where(|key, _| before(key))[0].0
+    }
+
+    fn find(&self, key: &K) -> Slot {
+        let mut at = None;
+        for level in (0..=self.max_level).rev() {
+            while let Some(next) = self.next_of(at, level) {
+                match self.node(next).key.cmp(key) {
+                    Ordering::Less => at = Some(next),
+                    Ordering::Equal => return Some(next),
+                    Ordering::Greater => break,
+                }
             }
-            self.tails[i] = Some(new.clone());
         }
+        None
+    }
 
-        // this is the first node in the list
-        if self.head.is_none() {
-            self.head = Some(new.clone());
+    // Takes `slot`, which `path` leads up to, out of every level.
+    fn unlink(&mut self, path: &[(Slot, usize)], slot: usize) -> SkipNode<K, V> {
+        let node = self.release(slot);
+        if let Some(next) = node.next[0] {
+            self.node_mut(next).prev = node.prev;
         }
-        self.length += 1;
+        for (l, &(at, _)) in path.iter().enumerate() {
+            let span = self.span_mut(at, l);
+            if l >= node.next.len() {
+                *span -= 1;
+                continue;
+            }
+            *span += node.span[l];
+            *span -= 1;
+            self.set_next(at, l, node.next[l]);
+            if self.tails[l] == Some(slot) {
+                self.tails[l] = at;
+            }
+        }
+        node
     }
 
-    pub fn find(&self, offset: u64) -> Option<String> {
-        match self.head {
-            Some(ref head) => {
-                let mut start_level = self.max_level;
-                let node = head.clone();
-                let mut result = None;
-                loop {
-                    if node.borrow().next[start_level].is_some() {
-                        break;
-                    }
-                    start_level -= 1;
-                }
-                let mut n = node;
-                for level in (0..=start_level).rev() {
-                    loop {
-                        let next = n.clone();
-                        match next.borrow().next[level] {
-                            Some(ref next) if next.borrow().offset <= offset => n = next.clone(),
-                            _ => break,
-                        };
-                    }
-                    if n.borrow().offset == offset {
-                        let tmp = n.borrow();
-                        result = Some(tmp.command.clone());
-                        break;
-                    }
-                }
-                result
+    // Takes a node out of the arena and frees its slot. Linking around it
+    // is up to the caller.
+    fn release(&mut self, slot: usize) -> SkipNode<K, V> {
+        let node = self.nodes[slot]
+            .take()
+            .expect("links only point at live nodes");
+        self.free.push(slot);
+        self.length -= 1;
+        node
+    }
+
+    fn alloc(&mut self, node: SkipNode<K, V>) -> usize {
+        match self.free.pop() {
+            Some(slot) => {
+                self.nodes[slot] = Some(node);
+                slot
             }
-            None => None,
+            None => {
+                self.nodes.push(Some(node));
+                self.nodes.len() - 1
+            }
+        }
+    }
+}
+
+impl<K, V> SkipMap<K, V> {
+    fn node(&self, slot: usize) -> &SkipNode<K, V> {
+        self.nodes[slot]
+            .as_ref()
+            .expect("links only point at live nodes")
+    }
+
+    fn node_mut(&mut self, slot: usize) -> &mut SkipNode<K, V> {
+        self.nodes[slot]
+            .as_mut()
+            .expect("links only point at live nodes")
+    }
+
+    fn entry(&self, slot: usize) -> (&K, &V) {
+        let node = self.node(slot);
+        (&node.key, &node.value)
+    }
+
+    // The keys linked on `level`, in order, each with the span of the link
+    // leading to it.
+    fn level_links(&self, level: usize) -> impl Iterator<Item = (&K, usize)> {
+        let mut at = None;
+        std::iter::from_fn(move || {
+            let next = self.next_of(at, level)?;
+            let span = self.span_of(at, level);
+            at = Some(next);
+            Some((&self.node(next).key, span))
+        })
+    }
+
+    fn next_of(&self, at: Slot, level: usize) -> Slot {
+        match at {
+            Some(at) => self.node(at).next[level],
+            None => self.head[level],
         }
     }
 
-    fn iter_level(&self, level: usize) -> ListIterator {
-        ListIterator::new(self.head.clone(), level)
+    fn set_next(&mut self, at: Slot, level: usize, to: Slot) {
+        match at {
+            Some(at) => self.node_mut(at).next[level] = to,
+            None => self.head[level] = to,
+        }
+    }
+
+    fn span_of(&self, at: Slot, level: usize) -> usize {
+        match at {
+            Some(at) => self.node(at).span[level],
+            None => self.head_span[level],
+        }
+    }
+
+    fn span_mut(&mut self, at: Slot, level: usize) -> &mut usize {
+        match at {
+            Some(at) => &mut self.node_mut(at).span[level],
+            None => &mut self.head_span[level],
+        }
     }
 }
 
-impl IntoIterator for BestTransactionLog {
-    type Item = (u64, String);
-    type IntoIter = ListIterator;
+impl<K: fmt::Debug + Ord, V: fmt::Debug> fmt::Debug for SkipMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<'a, K: Ord, V> IntoIterator for &'a SkipMap<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
 
     fn into_iter(self) -> Self::IntoIter {
-        ListIterator::new(self.head, 0)
+        self.iter()
     }
 }
 
-pub struct ListIterator {
-    current: Link,
-    level: usize,
+impl<K, V> IntoIterator for SkipMap<K, V> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter {
+            next: self.head[0],
+            map: self,
+        }
+    }
 }
 
-impl ListIterator {
-    fn new(start_at: Link, level: usize) -> Self {
+/// Borrowing iterator over a `SkipMap` in key order. It walks in from both
+/// ends and stops once they meet.
+pub struct Iter<'a, K, V> {
+    map: &'a SkipMap<K, V>,
+    front: Slot,
+    back: Slot,
+    remaining: usize,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let slot = self.front?;
+        self.front = self.map.node(slot).next[0];
+        self.remaining -= 1;
+        Some(self.map.entry(slot))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K, V> DoubleEndedIterator for Iter<'_, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let slot = self.back?;
+        self.back = self.map.node(slot).prev;
+        self.remaining -= 1;
+        Some(self.map.entry(slot))
+    }
+}
+
+impl<K, V> ExactSizeIterator for Iter<'_, K, V> {}
+
+/// Borrowing iterator over a key range of a `SkipMap`, from `SkipMap::range`
+/// or `SkipMap::seek`. It walks in from both ends and stops once they meet.
+pub struct Range<'a, K, V> {
+    map: &'a SkipMap<K, V>,
+    front: Slot,
+    back: Slot,
+}
+
+impl<'a, K, V> Iterator for Range<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let slot = self.front?;
+        if self.front == self.back {
+            (self.front, self.back) = (None, None);
+        } else {
+            self.front = self.map.node(slot).next[0];
+        }
+        Some(self.map.entry(slot))
+    }
+}
+
+impl<K, V> DoubleEndedIterator for Range<'_, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let slot = self.back?;
+        if self.front == self.back {
+            (self.front, self.back) = (None, None);
+        } else {
+            self.back = self.map.node(slot).prev;
+        }
+        Some(self.map.entry(slot))
+    }
+}
+
+/// Owning iterator over a `SkipMap` in key order.
+pub struct IntoIter<K, V> {
+    map: SkipMap<K, V>,
+    next: Slot,
+}
+
+impl<K, V> Iterator for IntoIter<K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let slot = self.next?;
+        let node = self.map.nodes[slot]
+            .take()
+            .expect("links only point at live nodes");
+        self.next = node.next[0];
+        self.map.length -= 1;
+        Some((node.key, node.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.map.length, Some(self.map.length))
+    }
+}
+
+impl<K, V> ExactSizeIterator for IntoIter<K, V> {}
+
+/// What `BestTransactionLog::append` does with an offset it already holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// hand the command back to the caller
+    Reject,
+    /// keep the new command in place of the old one
+    Replace,
+}
+
+/// The command `BestTransactionLog::append` turned away because its offset
+/// was already taken.
+#[derive(Debug, PartialEq, Eq)]
+pub struct DuplicateOffset(pub u64, pub String);
+
+/// A transaction log kept sorted by offset on a `SkipMap`, so late
+/// transactions can arrive out of order and still land in their place.
+#[derive(Clone)]
+pub struct BestTransactionLog {
+    entries: SkipMap<u64, String>,
+    duplicates: DuplicatePolicy,
+    pub length: u64,
+}
+
+impl BestTransactionLog {
+    pub fn new_empty(max_level: usize, duplicates: DuplicatePolicy) -> Self {
         Self {
-            current: start_at,
-            level,
+            entries: SkipMap::new(max_level),
+            duplicates,
+            length: 0,
+        }
+    }
+
+    pub fn append(&mut self, offset: u64, value: String) -> Result<(), DuplicateOffset> {
+        if self.duplicates == DuplicatePolicy::Reject && self.entries.contains_key(&offset) {
+            return Err(DuplicateOffset(offset, value));
+        }
+        if self.entries.insert(offset, value).is_none() {
+            self.length += 1;
         }
+        Ok(())
+    }
+
+    pub fn find(&self, offset: u64) -> Option<String> {
+        self.entries.get(&offset).cloned()
+    }
+
+    pub fn remove(&mut self, offset: u64) -> Option<String> {
+        let command = self.entries.remove(&offset)?;
+        self.length -= 1;
+        Some(command)
+    }
+
+    /// Compacts the log down to the transactions from `offset` on.
+    pub fn truncate_before(&mut self, offset: u64) {
+        self.entries.truncate_before(&offset);
+        self.length = self.entries.len() as u64;
+    }
+
+    /// Rolls the log back to the transactions up to and including `offset`.
+    pub fn truncate_after(&mut self, offset: u64) {
+        self.entries.truncate_after(&offset);
+        self.length = self.entries.len() as u64;
+    }
+
+    /// The transaction at `index` in offset order.
+    pub fn nth(&self, index: u64) -> Option<(u64, String)> {
+        self.entries
+            .nth(index as usize)
+            .map(|(offset, command)| (*offset, command.clone()))
+    }
+
+    /// How many transactions precede `offset`.
+    pub fn rank(&self, offset: u64) -> u64 {
+        self.entries.rank(&offset) as u64
+    }
+
+    /// Removes the transaction at `index` in offset order.
+    pub fn remove_at(&mut self, index: u64) -> Option<(u64, String)> {
+        let removed = self.entries.remove_at(index as usize)?;
+        self.length -= 1;
+        Some(removed)
+    }
+
+    /// Replays the transactions with offsets in `range`, in order or, from
+    /// the back, in reverse.
+    pub fn range(&self, range: impl RangeBounds<u64>) -> ListIterator<'_> {
+        ListIterator {
+            entries: self.entries.range(range),
+        }
+    }
+
+    /// Replays the transactions from `offset` on.
+    pub fn seek(&self, offset: u64) -> ListIterator<'_> {
+        self.range(offset..)
     }
 }
 
-impl Iterator for ListIterator {
+/// Iterator over a stretch of a `BestTransactionLog`, handing out
+/// `(offset, command)` pairs.
+pub struct ListIterator<'a> {
+    entries: Range<'a, u64, String>,
+}
+
+impl Iterator for ListIterator<'_> {
     type Item = (u64, String);
 
     fn next(&mut self) -> Option<(u64, String)> {
-        let current = &self.current;
-        let mut result = None;
-        self.current = match current {
-            Some(ref current) => {
-                let current = current.borrow();
-                result = Some((current.offset, current.command.clone()));
-                current.next[self.level].clone()
-            }
-            _ => None,
-        };
-        result
+        self.entries
+            .next()
+            .map(|(offset, command)| (*offset, command.clone()))
     }
 }
 
+impl DoubleEndedIterator for ListIterator<'_> {
+    fn next_back(&mut self) -> Option<(u64, String)> {
+        self.entries
+            .next_back()
+            .map(|(offset, command)| (*offset, command.clone()))
+    }
+}
+
+impl IntoIterator for BestTransactionLog {
+    type Item = (u64, String);
+    type IntoIter = IntoIter<u64, String>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+// Prints every level on its own line, top one first, with the number of
+// entries each link jumps over: `1: -2-> [20] -1-> [30]`.
 impl std::fmt::Debug for BestTransactionLog {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self.head {
-            Some(ref _head) => {
-                for level in (0..=self.max_level).rev() {
-                    let _ = write!(f, "{}: ", level);
-                    for n in self.iter_level(level) {
-                        let _ = write!(f, "[{}] ", n.0);
-                    }
-                    let _ = writeln!(f, "");
-                }
-                Ok(())
+        if self.entries.is_empty() {
+            return write!(f, "The list is empty: []");
+        }
+        for level in (0..=self.entries.max_level).rev() {
+            write!(f, "{}: ", level)?;
+            for (offset, span) in self.entries.level_links(level) {
+                write!(f, "-{}-> [{}] ", span, offset)?;
             }
-            None => write!(f, "The list is empty: []"),
+            writeln!(f)?;
         }
+        Ok(())
     }
 }
 
 fn main() {
     println!("Aum Namah Sivaya!!! Skip List");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::collections::BTreeMap;
+
+    // The generator for the randomised tests. The seed is printed, and a
+    // failing run is replayed by setting `TEST_SEED` to it.
+    fn seeded_rng() -> StdRng {
+        let seed = std::env::var("TEST_SEED")
+            .ok()
+            .and_then(|seed| seed.parse().ok())
+            .unwrap_or_else(rand::random);
+        println!("TEST_SEED={}", seed);
+        StdRng::seed_from_u64(seed)
+    }
+
+    // every level has to be sorted, a subset of the one below, and end at
+    // its tail, the bottom one has to link back the same way, and every span
+    // has to match the ranks it jumps between
+    fn assert_levels<K: Ord + fmt::Debug + Clone, V>(map: &SkipMap<K, V>) {
+        let mut below: Vec<K> = Vec::new();
+        for level in 0..=map.max_level {
+            let mut keys = Vec::new();
+            let mut at = map.head[level];
+            let mut last = None;
+            while let Some(slot) = at {
+                keys.push(map.node(slot).key.clone());
+                if level == 0 {
+                    assert_eq!(map.node(slot).prev, last);
+                }
+                last = Some(slot);
+                at = map.node(slot).next[level];
+            }
+            assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+            assert_eq!(map.tails[level], last);
+            let mut rank = 0;
+            for (key, span) in map.level_links(level) {
+                rank += span;
+                assert_eq!(map.nth(rank - 1).map(|(k, _)| k), Some(key));
+            }
+            assert_eq!(map.span_of(last, level), map.len() - rank);
+            if level == 0 {
+                assert_eq!(keys.len(), map.len());
+            } else {
+                assert!(keys.iter().all(|key| below.binary_search(key).is_ok()));
+            }
+            below = keys;
+        }
+    }
+
+    #[test]
+    fn skip_map_insert_get_remove() {
+        let mut map = SkipMap::new(4);
+        assert!(map.is_empty());
+        assert_eq!(map.insert(3, "Shambho"), None);
+        assert_eq!(map.insert(1, "Siva"), None);
+        assert_eq!(map.insert(2, "Hara"), None);
+        assert_eq!(map.insert(2, "Mahadev"), Some("Hara"));
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.get(&2), Some(&"Mahadev"));
+        *map.get_mut(&3).unwrap() = "Sambasiva";
+        assert_eq!(map.first(), Some((&1, &"Siva")));
+        assert_eq!(map.last(), Some((&3, &"Sambasiva")));
+        assert_eq!(map.remove(&3), Some("Sambasiva"));
+        assert_eq!(map.remove(&3), None);
+        assert!(!map.contains_key(&3));
+        assert_eq!(map.last(), Some((&2, &"Mahadev")));
+        assert_eq!(format!("{:?}", map), r#"{1: "Siva", 2: "Mahadev"}"#);
+        assert_levels(&map);
+    }
+
+    #[test]
+    fn skip_map_against_btree_map() {
+        let mut rng = seeded_rng();
+        for max_level in [0, 3, 8] {
+            let mut map = SkipMap::new(max_level);
+            let mut oracle = BTreeMap::new();
+            for step in 0..2000u64 {
+                let key = rng.gen_range(0..200u64);
+                match rng.gen_range(0..6) {
+                    0 | 1 => assert_eq!(map.insert(key, step), oracle.insert(key, step)),
+                    2 => assert_eq!(map.remove(&key), oracle.remove(&key)),
+                    3 => {
+                        assert_eq!(map.get(&key), oracle.get(&key));
+                        assert_eq!(map.contains_key(&key), oracle.contains_key(&key));
+                    }
+                    4 => {
+                        if let Some(value) = map.get_mut(&key) {
+                            *value += 1;
+                        }
+                        if let Some(value) = oracle.get_mut(&key) {
+                            *value += 1;
+                        }
+                    }
+                    _ => {
+                        assert_eq!(map.first(), oracle.first_key_value());
+                        assert_eq!(map.last(), oracle.last_key_value());
+                    }
+                }
+                assert_eq!(map.len(), oracle.len());
+            }
+            assert!(map.iter().eq(oracle.iter()));
+            assert_eq!(map.iter().len(), oracle.len());
+            assert_levels(&map);
+        }
+    }
+
+    #[test]
+    fn skip_map_reuses_free_slots() {
+        let mut map = SkipMap::new(4);
+        for key in 0..10 {
+            map.insert(key, key);
+        }
+        for key in 0..5 {
+            map.remove(&key);
+        }
+        for key in 20..25 {
+            map.insert(key, key);
+        }
+        assert_eq!(map.nodes.len(), 10);
+        assert!(map.iter().map(|(k, _)| *k).eq((5..10).chain(20..25)));
+        assert_levels(&map);
+    }
+
+    #[test]
+    fn best_transaction_log_out_of_order_append() {
+        let mut log = BestTransactionLog::new_empty(4, DuplicatePolicy::Reject);
+        assert_eq!(format!("{:?}", log), "The list is empty: []");
+        for offset in [5, 1, 9, 3, 7] {
+            log.append(offset, format!("command {}", offset)).unwrap();
+        }
+        assert_eq!(log.length, 5);
+        assert_eq!(log.find(3), Some("command 3".to_owned()));
+        assert_eq!(log.find(4), None);
+        assert_levels(&log.entries);
+        let offsets: Vec<u64> = log.into_iter().map(|(offset, _)| offset).collect();
+        assert_eq!(offsets, [1, 3, 5, 7, 9]);
+    }
+
+    #[test]
+    fn best_transaction_log_duplicates() {
+        let mut rejecting = BestTransactionLog::new_empty(2, DuplicatePolicy::Reject);
+        rejecting.append(1, "Siva".to_owned()).unwrap();
+        assert_eq!(
+            rejecting.append(1, "Shambho".to_owned()),
+            Err(DuplicateOffset(1, "Shambho".to_owned()))
+        );
+        assert_eq!(rejecting.find(1), Some("Siva".to_owned()));
+
+        let mut replacing = BestTransactionLog::new_empty(2, DuplicatePolicy::Replace);
+        replacing.append(1, "Siva".to_owned()).unwrap();
+        replacing.append(1, "Shambho".to_owned()).unwrap();
+        assert_eq!(replacing.length, 1);
+        assert_eq!(replacing.find(1), Some("Shambho".to_owned()));
+    }
+
+    #[test]
+    fn best_transaction_log_debug_levels() {
+        let mut log = BestTransactionLog::new_empty(0, DuplicatePolicy::Reject);
+        for offset in [3, 1, 2] {
+            log.append(offset, String::new()).unwrap();
+        }
+        assert_eq!(format!("{:?}", log), "0: -1-> [1] -1-> [2] -1-> [3] \n");
+
+        let mut log = BestTransactionLog::new_empty(3, DuplicatePolicy::Reject);
+        for offset in (0..50).rev() {
+            log.append(offset, String::new()).unwrap();
+        }
+        let printed = format!("{:?}", log);
+        let lines: Vec<&str> = printed.lines().collect();
+        assert_eq!(lines.len(), 4);
+        let expected: String = (0..50)
+            .map(|offset| format!("-1-> [{}] ", offset))
+            .collect();
+        assert_eq!(lines[3], format!("0: {}", expected));
+    }
+
+    #[test]
+    fn skip_map_truncate() {
+        let mut map = SkipMap::new(3);
+        for key in (0..20).step_by(2) {
+            map.insert(key, key);
+        }
+        map.truncate_before(&5);
+        assert_eq!(map.first(), Some((&6, &6)));
+        map.truncate_after(&12);
+        assert_eq!(map.last(), Some((&12, &12)));
+        assert!(map.iter().map(|(k, _)| *k).eq([6, 8, 10, 12]));
+        assert_levels(&map);
+        map.truncate_before(&100);
+        assert!(map.is_empty());
+        assert_levels(&map);
+        map.insert(1, 1);
+        map.truncate_after(&0);
+        assert!(map.is_empty());
+        assert_eq!(map.nodes.iter().flatten().count(), 0);
+        assert_levels(&map);
+    }
+
+    #[test]
+    fn best_transaction_log_remove_and_truncate() {
+        let mut rng = seeded_rng();
+        for _ in 0..20 {
+            let mut log = BestTransactionLog::new_empty(4, DuplicatePolicy::Replace);
+            let mut oracle = BTreeMap::new();
+            for step in 0..400 {
+                let offset = rng.gen_range(0..300u64);
+                match rng.gen_range(0..10) {
+                    0..=5 => {
+                        log.append(offset, step.to_string()).unwrap();
+                        oracle.insert(offset, step.to_string());
+                    }
+                    6..=7 => assert_eq!(log.remove(offset), oracle.remove(&offset)),
+                    8 => {
+                        log.truncate_before(offset);
+                        oracle = oracle.split_off(&offset);
+                    }
+                    _ => {
+                        log.truncate_after(offset);
+                        oracle.split_off(&(offset + 1));
+                    }
+                }
+                assert_eq!(log.length, oracle.len() as u64);
+                assert_eq!(log.find(offset), oracle.get(&offset).cloned());
+            }
+            assert_levels(&log.entries);
+            let printed = format!("{:?}", log);
+            let bottom = printed.lines().last().unwrap_or(&printed);
+            let expected: String = oracle
+                .keys()
+                .map(|offset| format!("-1-> [{}] ", offset))
+                .collect();
+            if oracle.is_empty() {
+                assert_eq!(bottom, "The list is empty: []");
+            } else {
+                assert_eq!(bottom, format!("0: {}", expected));
+                // every level above lists a sorted subset of the one below
+                let levels: Vec<Vec<u64>> = printed
+                    .lines()
+                    .map(|line| {
+                        line.split(['[', ']', ' '])
+                            .skip(2)
+                            .filter_map(|offset| offset.parse().ok())
+                            .collect()
+                    })
+                    .collect();
+                assert_eq!(levels.len(), 5);
+                for pair in levels.windows(2) {
+                    assert!(pair[0].windows(2).all(|w| w[0] < w[1]));
+                    assert!(pair[0].iter().all(|offset| pair[1].contains(offset)));
+                }
+            }
+            assert!(log.into_iter().eq(oracle));
+        }
+    }
+
+    #[test]
+    fn skip_map_range_against_btree_map() {
+        let mut rng = seeded_rng();
+        let mut map = SkipMap::new(5);
+        let mut oracle = BTreeMap::new();
+        for _ in 0..300 {
+            let key = rng.gen_range(0..500u64);
+            map.insert(key, key * 2);
+            oracle.insert(key, key * 2);
+        }
+        for _ in 0..300 {
+            let (a, b) = (rng.gen_range(0..520u64), rng.gen_range(0..520u64));
+            let (lo, hi) = (a.min(b), a.max(b));
+            assert!(map.range(lo..hi).eq(oracle.range(lo..hi)));
+            assert!(map.range(lo..=hi).rev().eq(oracle.range(lo..=hi).rev()));
+            let bounds = (Bound::Excluded(lo), Bound::Included(hi));
+            assert!(map.range(bounds).eq(oracle.range(bounds)));
+            assert!(map.range(..hi).rev().eq(oracle.range(..hi).rev()));
+            assert!(map.seek(&lo).eq(oracle.range(lo..)));
+
+            // taking from both ends meets in the middle without overlap
+            let mut range = map.range(lo..=hi);
+            let mut expected = oracle.range(lo..=hi);
+            loop {
+                let from_front = rng.gen_bool(0.5);
+                let (got, want) = if from_front {
+                    (range.next(), expected.next())
+                } else {
+                    (range.next_back(), expected.next_back())
+                };
+                assert_eq!(got, want);
+                if got.is_none() {
+                    break;
+                }
+            }
+        }
+        assert!(map.iter().rev().eq(oracle.iter().rev()));
+        assert!(map
+            .range((Bound::Included(10), Bound::Excluded(5)))
+            .next()
+            .is_none());
+    }
+
+    #[test]
+    fn best_transaction_log_range_and_seek() {
+        let mut log = BestTransactionLog::new_empty(3, DuplicatePolicy::Reject);
+        for offset in [40, 10, 30, 20, 50] {
+            log.append(offset, format!("command {}", offset)).unwrap();
+        }
+        let replayed: Vec<(u64, String)> = log.range(15..40).collect();
+        assert_eq!(
+            replayed,
+            [(20, "command 20".to_owned()), (30, "command 30".to_owned())]
+        );
+        let offsets: Vec<u64> = log.range(..=30).rev().map(|(offset, _)| offset).collect();
+        assert_eq!(offsets, [30, 20, 10]);
+        let offsets: Vec<u64> = log.seek(35).map(|(offset, _)| offset).collect();
+        assert_eq!(offsets, [40, 50]);
+        assert_eq!(log.seek(60).next(), None);
+        log.remove(40);
+        log.truncate_before(20);
+        let offsets: Vec<u64> = log.seek(0).rev().map(|(offset, _)| offset).collect();
+        assert_eq!(offsets, [50, 30, 20]);
+    }
+
+    #[test]
+    fn skip_map_rank_and_select() {
+        let mut rng = seeded_rng();
+        for max_level in [0, 4] {
+            let mut map = SkipMap::new(max_level);
+            let mut oracle: Vec<u64> = Vec::new();
+            for _ in 0..1500 {
+                let key = rng.gen_range(0..300u64);
+                let at = oracle.partition_point(|&other| other < key);
+                let present = oracle.get(at) == Some(&key);
+                match rng.gen_range(0..8) {
+                    0..=2 => {
+                        map.insert(key, key);
+                        if !present {
+                            oracle.insert(at, key);
+                        }
+                    }
+                    3 => {
+                        map.remove(&key);
+                        if present {
+                            oracle.remove(at);
+                        }
+                    }
+                    4 => {
+                        let index = rng.gen_range(0..=oracle.len());
+                        let expected = (index < oracle.len()).then(|| oracle.remove(index));
+                        assert_eq!(map.remove_at(index).map(|(k, _)| k), expected);
+                    }
+                    5 => {
+                        map.truncate_before(&key);
+                        oracle.drain(..at);
+                    }
+                    6 => {
+                        map.truncate_after(&key);
+                        oracle.truncate(at + present as usize);
+                    }
+                    _ => {
+                        assert_eq!(map.rank(&key), at);
+                        let index = rng.gen_range(0..=oracle.len());
+                        assert_eq!(map.nth(index).map(|(k, _)| k), oracle.get(index));
+                    }
+                }
+            }
+            assert!(map.iter().map(|(k, _)| *k).eq(oracle.iter().copied()));
+            assert_levels(&map);
+        }
+    }
+
+    #[test]
+    fn best_transaction_log_nth_rank_remove_at() {
+        let mut log = BestTransactionLog::new_empty(3, DuplicatePolicy::Reject);
+        for offset in [40, 10, 30, 20, 50] {
+            log.append(offset, format!("command {}", offset)).unwrap();
+        }
+        assert_eq!(log.nth(2), Some((30, "command 30".to_owned())));
+        assert_eq!(log.nth(5), None);
+        assert_eq!(log.rank(30), 2);
+        assert_eq!(log.rank(35), 3);
+        assert_eq!(log.rank(0), 0);
+        assert_eq!(log.remove_at(1), Some((20, "command 20".to_owned())));
+        assert_eq!(log.remove_at(4), None);
+        assert_eq!(log.length, 4);
+        assert_eq!(log.rank(50), 3);
+        assert_levels(&log.entries);
+
+        let mut log = BestTransactionLog::new_empty(1, DuplicatePolicy::Reject);
+        for offset in 1..=3 {
+            log.append(offset, String::new()).unwrap();
+        }
+        let printed = format!("{:?}", log);
+        assert_eq!(
+            printed.lines().last(),
+            Some("0: -1-> [1] -1-> [2] -1-> [3] ")
+        );
+        // the spans on the top line add up to the rank of its last entry
+        let top = printed.lines().next().unwrap();
+        let spans: u64 = top
+            .split(' ')
+            .filter_map(|link| {
+                link.strip_prefix('-')?
+                    .strip_suffix("->")?
+                    .parse::<u64>()
+                    .ok()
+            })
+            .sum();
+        let last = top
+            .split(['[', ']'])
+            .filter_map(|offset| offset.parse::<u64>().ok())
+            .next_back();
+        assert_eq!(spans, last.map_or(0, |offset| log.rank(offset) + 1));
+    }
+}
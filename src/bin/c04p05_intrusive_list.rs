@@ -0,0 +1,665 @@
+use std::cell::Cell;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ptr::NonNull;
+
+type Link<T> = Option<NonNull<T>>;
+
+/// The link fields a struct embeds once for every list it can be a member
+/// of at the same time.
+///
+/// The list never allocates: it only borrows its members and threads them
+/// together through these fields, which is why they live in `Cell`s.
+pub struct Links<T> {
+    next: Cell<Link<T>>,
+    prev: Cell<Link<T>>,
+    linked: Cell<bool>,
+}
+
+impl<T> Links<T> {
+    pub const fn new() -> Self {
+        Self {
+            next: Cell::new(None),
+            prev: Cell::new(None),
+            linked: Cell::new(false),
+        }
+    }
+
+    pub fn is_linked(&self) -> bool {
+        self.linked.get()
+    }
+}
+
+impl<T> Default for Links<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> fmt::Debug for Links<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Links")
+            .field("linked", &self.is_linked())
+            .finish()
+    }
+}
+
+/// Picks the `Links` field an `IntrusiveTransactionLog` threads its members
+/// through.
+///
+/// # Safety
+///
+/// `links` has to return the same field of the value every time, and no two
+/// adapters used at the same time may pick the same field.
+pub unsafe trait Adapter {
+    type Value;
+
+    fn links(value: &Self::Value) -> &Links<Self::Value>;
+}
+
+/// An intrusive doubly linked list with the same iterators and cursors as
+/// `BetterTransactionLog`.
+///
+/// Members are borrowed for `'a`, so they can't move or be dropped while they
+/// are linked, and every list they are in needs a `Links` field of its own.
+pub struct IntrusiveTransactionLog<'a, A: Adapter> {
+    head: Link<A::Value>,
+    tail: Link<A::Value>,
+    pub length: u64,
+    _members: PhantomData<&'a A::Value>,
+}
+
+impl<'a, A: Adapter> IntrusiveTransactionLog<'a, A> {
+    pub fn new_empty() -> Self {
+        Self {
+            head: None,
+            tail: None,
+            length: 0,
+            _members: PhantomData,
+        }
+    }
+
+    /// Panics if `value` is already in a list through this adapter.
+    pub fn append(&mut self, value: &'a A::Value) {
+        self.link(value, self.tail, None);
+    }
+
+    /// Panics if `value` is already in a list through this adapter.
+    pub fn push_front(&mut self, value: &'a A::Value) {
+        self.link(value, None, self.head);
+    }
+
+    pub fn pop(&mut self) -> Option<&'a A::Value> {
+        // SAFETY: `head` is a member of this list
+        self.head.map(|head| unsafe { self.unlink(head) })
+    }
+
+    pub fn pop_back(&mut self) -> Option<&'a A::Value> {
+        // SAFETY: `tail` is a member of this list
+        self.tail.map(|tail| unsafe { self.unlink(tail) })
+    }
+
+    pub fn peek_front(&self) -> Option<&'a A::Value> {
+        // SAFETY: `head` is a member of this list
+        self.head.map(|head| unsafe { member(head) })
+    }
+
+    pub fn peek_back(&self) -> Option<&'a A::Value> {
+        // SAFETY: `tail` is a member of this list
+        self.tail.map(|tail| unsafe { member(tail) })
+    }
+
+    /// Takes `value` out of the list in O(1).
+    ///
+    /// # Safety
+    ///
+    /// `value` has to be a member of this very list, not just of any list
+    /// using the same adapter.
+    pub unsafe fn remove(&mut self, value: &'a A::Value) {
+        self.unlink(NonNull::from(value));
+    }
+
+    /// Unlinks every member, so they can join other lists again.
+    pub fn clear(&mut self) {
+        while self.pop().is_some() {}
+    }
+
+    /// Iterates from the tail to the head.
+    pub fn back_iter(&self) -> std::iter::Rev<ListIterator<'_, 'a, A>> {
+        self.iter().rev()
+    }
+
+    pub fn iter(&self) -> ListIterator<'_, 'a, A> {
+        ListIterator {
+            head: self.head,
+            tail: self.tail,
+            length: self.length,
+            _list: PhantomData,
+        }
+    }
+
+    pub fn cursor_front(&self) -> Cursor<'_, 'a, A> {
+        Cursor {
+            current: self.head,
+            index: 0,
+            list: self,
+        }
+    }
+
+    pub fn cursor_back(&self) -> Cursor<'_, 'a, A> {
+        Cursor {
+            current: self.tail,
+            index: self.length.saturating_sub(1),
+            list: self,
+        }
+    }
+
+    pub fn cursor_front_mut(&mut self) -> CursorMut<'_, 'a, A> {
+        CursorMut {
+            current: self.head,
+            index: 0,
+            list: self,
+        }
+    }
+
+    pub fn cursor_back_mut(&mut self) -> CursorMut<'_, 'a, A> {
+        CursorMut {
+            current: self.tail,
+            index: self.length.saturating_sub(1),
+            list: self,
+        }
+    }
+
+    // Links `value` in between `prev` and `next`, which have to be adjacent
+    // members of this list (`None` standing for either end).
+    fn link(&mut self, value: &'a A::Value, prev: Link<A::Value>, next: Link<A::Value>) {
+        let links = A::links(value);
+        assert!(!links.is_linked(), "the value is already in a list");
+        let node = NonNull::from(value);
+        links.linked.set(true);
+        links.prev.set(prev);
+        links.next.set(next);
+        // SAFETY: `prev` and `next` are members of this list
+        unsafe {
+            match prev {
+                Some(prev) => A::links(member(prev)).next.set(Some(node)),
+                None => self.head = Some(node),
+            }
+            match next {
+                Some(next) => A::links(member(next)).prev.set(Some(node)),
+                None => self.tail = Some(node),
+            }
+        }
+        self.length += 1;
+    }
+
+    // Takes a member out of this list.
+    unsafe fn unlink(&mut self, node: NonNull<A::Value>) -> &'a A::Value {
+        let value = member(node);
+        let links = A::links(value);
+        let (prev, next) = (links.prev.take(), links.next.take());
+        match prev {
+            Some(prev) => A::links(member(prev)).next.set(next),
+            None => self.head = next,
+        }
+        match next {
+            Some(next) => A::links(member(next)).prev.set(prev),
+            None => self.tail = prev,
+        }
+        links.linked.set(false);
+        self.length -= 1;
+        value
+    }
+
+    // Moves every member of `other` in between the adjacent `prev` and `next`.
+    unsafe fn splice(
+        &mut self,
+        prev: Link<A::Value>,
+        next: Link<A::Value>,
+        mut other: IntrusiveTransactionLog<'a, A>,
+    ) {
+        let (Some(first), Some(last)) = (other.head.take(), other.tail.take()) else {
+            return;
+        };
+        A::links(member(first)).prev.set(prev);
+        A::links(member(last)).next.set(next);
+        match prev {
+            Some(prev) => A::links(member(prev)).next.set(Some(first)),
+            None => self.head = Some(first),
+        }
+        match next {
+            Some(next) => A::links(member(next)).prev.set(Some(last)),
+            None => self.tail = Some(last),
+        }
+        self.length += other.length;
+        other.length = 0;
+    }
+}
+
+// SAFETY: callers pass pointers to members of a list borrowed for 'a
+unsafe fn member<'a, T>(node: NonNull<T>) -> &'a T {
+    &*node.as_ptr()
+}
+
+impl<A: Adapter> Drop for IntrusiveTransactionLog<'_, A> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+impl<A: Adapter> fmt::Debug for IntrusiveTransactionLog<'_, A>
+where
+    A::Value: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<'l, 'a, A: Adapter> IntoIterator for &'l IntrusiveTransactionLog<'a, A> {
+    type Item = &'a A::Value;
+    type IntoIter = ListIterator<'l, 'a, A>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Borrowing iterator over an `IntrusiveTransactionLog`. It walks in from
+/// both ends and stops once they meet.
+pub struct ListIterator<'l, 'a, A: Adapter> {
+    head: Link<A::Value>,
+    tail: Link<A::Value>,
+    length: u64,
+    _list: PhantomData<&'l IntrusiveTransactionLog<'a, A>>,
+}
+
+impl<'a, A: Adapter> Iterator for ListIterator<'_, 'a, A> {
+    type Item = &'a A::Value;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.length == 0 {
+            return None;
+        }
+        self.head.map(|head| {
+            // SAFETY: the list is borrowed, so its members stay linked
+            let head = unsafe { member(head) };
+            self.length -= 1;
+            self.head = A::links(head).next.get();
+            head
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.length as usize, Some(self.length as usize))
+    }
+}
+
+impl<A: Adapter> DoubleEndedIterator for ListIterator<'_, '_, A> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.length == 0 {
+            return None;
+        }
+        self.tail.map(|tail| {
+            // SAFETY: as in `next`
+            let tail = unsafe { member(tail) };
+            self.length -= 1;
+            self.tail = A::links(tail).prev.get();
+            tail
+        })
+    }
+}
+
+impl<A: Adapter> ExactSizeIterator for ListIterator<'_, '_, A> {}
+
+/// A read-only position in an `IntrusiveTransactionLog`, with the same
+/// ghost position between tail and head as `BetterTransactionLog` cursors.
+pub struct Cursor<'l, 'a, A: Adapter> {
+    current: Link<A::Value>,
+    index: u64,
+    list: &'l IntrusiveTransactionLog<'a, A>,
+}
+
+impl<'a, A: Adapter> Cursor<'_, 'a, A> {
+    /// The position of the current member, `None` on the ghost.
+    pub fn index(&self) -> Option<u64> {
+        self.current.map(|_| self.index)
+    }
+
+    pub fn move_next(&mut self) {
+        (self.current, self.index) = step_next(self.list, self.current, self.index);
+    }
+
+    pub fn move_prev(&mut self) {
+        (self.current, self.index) = step_prev(self.list, self.current, self.index);
+    }
+
+    pub fn current(&self) -> Option<&'a A::Value> {
+        // SAFETY: `current` is a member of the borrowed list
+        self.current.map(|current| unsafe { member(current) })
+    }
+
+    pub fn peek_next(&self) -> Option<&'a A::Value> {
+        // SAFETY: as in `current`
+        step_next(self.list, self.current, self.index)
+            .0
+            .map(|next| unsafe { member(next) })
+    }
+
+    pub fn peek_prev(&self) -> Option<&'a A::Value> {
+        // SAFETY: as in `current`
+        step_prev(self.list, self.current, self.index)
+            .0
+            .map(|prev| unsafe { member(prev) })
+    }
+}
+
+/// A position in an `IntrusiveTransactionLog` that can edit the list around
+/// it. Every edit is O(1).
+pub struct CursorMut<'l, 'a, A: Adapter> {
+    current: Link<A::Value>,
+    index: u64,
+    list: &'l mut IntrusiveTransactionLog<'a, A>,
+}
+
+impl<'a, A: Adapter> CursorMut<'_, 'a, A> {
+    /// The position of the current member, `None` on the ghost.
+    pub fn index(&self) -> Option<u64> {
+        self.current.map(|_| self.index)
+    }
+
+    pub fn move_next(&mut self) {
+        (self.current, self.index) = step_next(self.list, self.current, self.index);
+    }
+
+    pub fn move_prev(&mut self) {
+        (self.current, self.index) = step_prev(self.list, self.current, self.index);
+    }
+
+    pub fn current(&self) -> Option<&'a A::Value> {
+        // SAFETY: `current` is a member of the borrowed list
+        self.current.map(|current| unsafe { member(current) })
+    }
+
+    pub fn peek_next(&self) -> Option<&'a A::Value> {
+        self.as_cursor().peek_next()
+    }
+
+    pub fn peek_prev(&self) -> Option<&'a A::Value> {
+        self.as_cursor().peek_prev()
+    }
+
+    /// A read-only cursor at the same position.
+    pub fn as_cursor(&self) -> Cursor<'_, 'a, A> {
+        Cursor {
+            current: self.current,
+            index: self.index,
+            list: self.list,
+        }
+    }
+
+    /// Inserts after the current member, or at the front on the ghost.
+    /// Panics if `value` is already in a list through this adapter.
+    pub fn insert_after(&mut self, value: &'a A::Value) {
+        let next = step_next(self.list, self.current, self.index).0;
+        self.list.link(value, self.current, next);
+        if self.current.is_none() {
+            self.index += 1;
+        }
+    }
+
+    /// Inserts before the current member, or at the back on the ghost.
+    /// Panics if `value` is already in a list through this adapter.
+    pub fn insert_before(&mut self, value: &'a A::Value) {
+        let prev = step_prev(self.list, self.current, self.index).0;
+        self.list.link(value, prev, self.current);
+        self.index += 1;
+    }
+
+    /// Unlinks the current member and moves on to the next one. Does
+    /// nothing on the ghost.
+    pub fn remove_current(&mut self) -> Option<&'a A::Value> {
+        let current = self.current?;
+        self.current = A::links(self.current()?).next.get();
+        // SAFETY: `current` is a member of the list
+        Some(unsafe { self.list.unlink(current) })
+    }
+
+    /// Moves every member of `other` after the current member, or to the
+    /// front on the ghost.
+    pub fn splice_after(&mut self, other: IntrusiveTransactionLog<'a, A>) {
+        let added = other.length;
+        let next = step_next(self.list, self.current, self.index).0;
+        // SAFETY: `current` and `next` are adjacent members of the list
+        unsafe { self.list.splice(self.current, next, other) };
+        if self.current.is_none() {
+            self.index += added;
+        }
+    }
+
+    /// Moves every member of `other` before the current member, or to the
+    /// back on the ghost.
+    pub fn splice_before(&mut self, other: IntrusiveTransactionLog<'a, A>) {
+        let added = other.length;
+        let prev = step_prev(self.list, self.current, self.index).0;
+        // SAFETY: `prev` and `current` are adjacent members of the list
+        unsafe { self.list.splice(prev, self.current, other) };
+        self.index += added;
+    }
+}
+
+fn step_next<A: Adapter>(
+    list: &IntrusiveTransactionLog<'_, A>,
+    current: Link<A::Value>,
+    index: u64,
+) -> (Link<A::Value>, u64) {
+    match current {
+        // SAFETY: `current` is a member of the list
+        Some(current) => (A::links(unsafe { member(current) }).next.get(), index + 1),
+        None => (list.head, 0),
+    }
+}
+
+fn step_prev<A: Adapter>(
+    list: &IntrusiveTransactionLog<'_, A>,
+    current: Link<A::Value>,
+    index: u64,
+) -> (Link<A::Value>, u64) {
+    match current {
+        // SAFETY: `current` is a member of the list
+        Some(current) => (
+            A::links(unsafe { member(current) }).prev.get(),
+            index.checked_sub(1).unwrap_or(list.length),
+        ),
+        None => (list.tail, list.length.saturating_sub(1)),
+    }
+}
+
+fn main() {
+    println!("Hara Hara Mahadev!!! Intrusive Doubly Linked List");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Transaction {
+        id: u64,
+        amount: i64,
+        by_arrival: Links<Transaction>,
+        by_account: Links<Transaction>,
+    }
+
+    impl fmt::Debug for Transaction {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "#{}", self.id)
+        }
+    }
+
+    struct ByArrival;
+
+    unsafe impl Adapter for ByArrival {
+        type Value = Transaction;
+
+        fn links(value: &Transaction) -> &Links<Transaction> {
+            &value.by_arrival
+        }
+    }
+
+    struct ByAccount;
+
+    unsafe impl Adapter for ByAccount {
+        type Value = Transaction;
+
+        fn links(value: &Transaction) -> &Links<Transaction> {
+            &value.by_account
+        }
+    }
+
+    fn transactions(n: u64) -> Vec<Transaction> {
+        (0..n)
+            .map(|id| Transaction {
+                id,
+                amount: id as i64 * 10,
+                ..Transaction::default()
+            })
+            .collect()
+    }
+
+    fn ids<'a>(iter: impl Iterator<Item = &'a Transaction>) -> Vec<u64> {
+        iter.map(|t| t.id).collect()
+    }
+
+    // walks the list both ways to check every link, `tail` and `length`
+    fn assert_list<A: Adapter<Value = Transaction>>(
+        list: &IntrusiveTransactionLog<'_, A>,
+        expected: &[u64],
+    ) {
+        assert_eq!(ids(list.iter()), expected);
+        let mut back = ids(list.back_iter());
+        back.reverse();
+        assert_eq!(back, expected);
+        assert_eq!(list.iter().len(), expected.len());
+        assert_eq!(list.length, expected.len() as u64);
+        assert_eq!(list.peek_front().map(|t| t.id), expected.first().copied());
+        assert_eq!(list.peek_back().map(|t| t.id), expected.last().copied());
+    }
+
+    #[test]
+    fn intrusive_transaction_log_append_pop() {
+        let all = transactions(3);
+        let mut list: IntrusiveTransactionLog<ByArrival> = IntrusiveTransactionLog::new_empty();
+        assert_eq!(list.pop().map(|t| t.id), None);
+        list.append(&all[1]);
+        list.append(&all[2]);
+        list.push_front(&all[0]);
+        assert_list(&list, &[0, 1, 2]);
+        assert!(all[0].by_arrival.is_linked());
+        assert!(!all[0].by_account.is_linked());
+        assert_eq!(list.pop().map(|t| t.id), Some(0));
+        assert_eq!(list.pop_back().map(|t| t.amount), Some(20));
+        assert!(!all[0].by_arrival.is_linked());
+        assert_list(&list, &[1]);
+        // a popped value can rejoin
+        list.append(&all[0]);
+        assert_list(&list, &[1, 0]);
+        assert_eq!(format!("{:?}", list), "[#1, #0]");
+    }
+
+    #[test]
+    fn intrusive_transaction_log_several_lists() {
+        let all = transactions(4);
+        let mut arrivals: IntrusiveTransactionLog<ByArrival> = IntrusiveTransactionLog::new_empty();
+        let mut account: IntrusiveTransactionLog<ByAccount> = IntrusiveTransactionLog::new_empty();
+        for t in &all {
+            arrivals.append(t);
+        }
+        account.append(&all[3]);
+        account.append(&all[1]);
+        assert_list(&arrivals, &[0, 1, 2, 3]);
+        assert_list(&account, &[3, 1]);
+        // SAFETY: `all[1]` is in `arrivals`
+        unsafe { arrivals.remove(&all[1]) };
+        assert_list(&arrivals, &[0, 2, 3]);
+        assert_list(&account, &[3, 1]);
+        drop(account);
+        assert!(all.iter().all(|t| !t.by_account.is_linked()));
+        assert_list(&arrivals, &[0, 2, 3]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn intrusive_transaction_log_double_link() {
+        let all = transactions(1);
+        let mut first: IntrusiveTransactionLog<ByArrival> = IntrusiveTransactionLog::new_empty();
+        let mut second: IntrusiveTransactionLog<ByArrival> = IntrusiveTransactionLog::new_empty();
+        first.append(&all[0]);
+        second.append(&all[0]);
+    }
+
+    #[test]
+    fn intrusive_transaction_log_double_ended_iterator() {
+        let all = transactions(4);
+        let mut list: IntrusiveTransactionLog<ByArrival> = IntrusiveTransactionLog::new_empty();
+        for t in &all {
+            list.append(t);
+        }
+        let mut iter = list.iter();
+        assert_eq!(iter.next().map(|t| t.id), Some(0));
+        assert_eq!(iter.next_back().map(|t| t.id), Some(3));
+        assert_eq!(iter.len(), 2);
+        assert_eq!(iter.next_back().map(|t| t.id), Some(2));
+        assert_eq!(iter.next().map(|t| t.id), Some(1));
+        assert!(iter.next().is_none());
+        assert!(iter.next_back().is_none());
+        assert_eq!(ids((&list).into_iter()), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn intrusive_transaction_log_cursor() {
+        let all = transactions(8);
+        let mut list: IntrusiveTransactionLog<ByArrival> = IntrusiveTransactionLog::new_empty();
+        list.append(&all[0]);
+        list.append(&all[3]);
+
+        let mut cursor = list.cursor_front_mut();
+        cursor.insert_after(&all[2]);
+        cursor.insert_before(&all[4]);
+        assert_eq!(cursor.index(), Some(1));
+        assert_eq!(cursor.peek_prev().map(|t| t.id), Some(4));
+        cursor.move_next();
+        assert_eq!(cursor.remove_current().map(|t| t.id), Some(2));
+        assert_eq!(cursor.current().map(|t| t.id), Some(3));
+        cursor.move_next();
+        assert_eq!(cursor.index(), None);
+        // on the ghost, after means the front and before means the back
+        cursor.insert_after(&all[5]);
+        cursor.insert_before(&all[6]);
+        let mut other = IntrusiveTransactionLog::new_empty();
+        other.append(&all[1]);
+        other.append(&all[7]);
+        cursor.move_prev();
+        cursor.splice_before(other);
+        assert_eq!(cursor.index(), Some(6));
+        assert_list(&list, &[5, 4, 0, 3, 1, 7, 6]);
+        assert!(!all[2].by_arrival.is_linked());
+
+        let mut cursor = list.cursor_back();
+        assert_eq!(cursor.index(), Some(6));
+        cursor.move_next();
+        assert_eq!(cursor.current().map(|t| t.id), None);
+        assert_eq!(cursor.peek_next().map(|t| t.id), Some(5));
+        cursor.move_prev();
+        cursor.move_prev();
+        assert_eq!(cursor.current().map(|t| t.id), Some(7));
+        assert_eq!(cursor.index(), Some(5));
+        let mut cursor = list.cursor_front();
+        cursor.move_prev();
+        assert_eq!(cursor.index(), None);
+
+        list.clear();
+        assert_list(&list, &[]);
+        assert!(all.iter().all(|t| !t.by_arrival.is_linked()));
+    }
+}
@@ -0,0 +1,442 @@
+use std::fmt;
+use std::sync::Arc;
+
+// Nodes are never changed once built, so every version of the log that
+// reaches a node can share it.
+struct Node<T> {
+    value: T,
+    next: Link<T>,
+}
+
+type Link<T> = Option<Arc<Node<T>>>;
+
+impl<T> Drop for Node<T> {
+    // Dropping a long chain recursively would overflow the stack, so a node
+    // releases the rest of its chain one node at a time, for as long as it
+    // holds the last reference. `Arc::into_inner` hands the node over to
+    // exactly one of several threads dropping their last references at once,
+    // so the chain is never left to a recursive drop.
+    fn drop(&mut self) {
+        let mut next = self.next.take();
+        while let Some(node) = next {
+            next = Arc::into_inner(node).and_then(|mut node| node.next.take());
+        }
+    }
+}
+
+// Entries appended at the back go onto a chain newest first, and every
+// `CHUNK` of them the chain is sealed into the trie, so no chain at the back
+// is ever longer than `CHUNK`.
+const CHUNK: usize = 32;
+const BITS: u32 = CHUNK.trailing_zeros();
+
+// A slot of the trie: a branch of up to `CHUNK` slots or, at the bottom, a
+// sealed chain of exactly `CHUNK` entries.
+enum Slot<T> {
+    Branch(Arc<Vec<Slot<T>>>),
+    Leaf(Arc<Node<T>>),
+}
+
+impl<T> Clone for Slot<T> {
+    fn clone(&self) -> Self {
+        match self {
+            Slot::Branch(branch) => Slot::Branch(branch.clone()),
+            Slot::Leaf(chain) => Slot::Leaf(chain.clone()),
+        }
+    }
+}
+
+/// A persistent transaction log: `append` and `push_front` leave `self`
+/// untouched and return a new version that shares every existing node with
+/// it, so a snapshot is just a `clone` and stays the same forever.
+///
+/// Entries pushed at the front are kept in a list in order. Entries appended
+/// at the back are kept in chains of `CHUNK`, newest first, under a trie of
+/// branches `CHUNK` wide. Both ends are O(1) amortised, and sealing a chain
+/// copies only the O(log n) branches on the path to it.
+pub struct PersistentTransactionLog<T> {
+    front: Link<T>,
+    // the end of `front`, so `peek_back` doesn't have to walk it
+    front_last: Link<T>,
+    // the sealed chains at the back, oldest first
+    root: Option<Arc<Vec<Slot<T>>>>,
+    // levels of branches in the trie, so a root of height 1 holds chains
+    height: u32,
+    leaves: u64,
+    // the newest entries at the back, not yet sealed
+    tail: Link<T>,
+    tail_length: usize,
+    // the oldest entry at the back
+    back_first: Link<T>,
+    pub length: u64,
+}
+
+impl<T> PersistentTransactionLog<T> {
+    pub fn new_empty() -> Self {
+        Self {
+            front: None,
+            front_last: None,
+            root: None,
+            height: 0,
+            leaves: 0,
+            tail: None,
+            tail_length: 0,
+            back_first: None,
+            length: 0,
+        }
+    }
+
+    /// A new version with `value` at the back.
+    pub fn append(&self, value: T) -> Self {
+        let mut log = self.clone();
+        if log.tail_length == CHUNK {
+            let chain = log.tail.take().expect("a full chain has a head");
+            log.seal(chain);
+            log.tail_length = 0;
+        }
+        let node = Arc::new(Node {
+            value,
+            next: log.tail.take(),
+        });
+        log.back_first.get_or_insert_with(|| node.clone());
+        log.tail = Some(node);
+        log.tail_length += 1;
+        log.length += 1;
+        log
+    }
+
+    /// A new version with `value` at the front.
+    pub fn push_front(&self, value: T) -> Self {
+        let mut log = self.clone();
+        let node = Arc::new(Node {
+            value,
+            next: log.front.take(),
+        });
+        log.front_last.get_or_insert_with(|| node.clone());
+        log.front = Some(node);
+        log.length += 1;
+        log
+    }
+
+    // Adds a full chain as the last leaf of the trie.
+    fn seal(&mut self, chain: Arc<Node<T>>) {
+        let leaf = Slot::Leaf(chain);
+        self.root = Some(match self.root.take() {
+            // every branch is full, so the trie grows a level
+            Some(root) if self.leaves == 1 << (BITS * self.height) => {
+                let path = Self::path(self.height, leaf);
+                self.height += 1;
+                Arc::new(vec![Slot::Branch(root), path])
+            }
+            Some(root) => Self::push_leaf(&root, self.height, self.leaves, leaf),
+            None => {
+                self.height = 1;
+                Arc::new(vec![leaf])
+            }
+        });
+        self.leaves += 1;
+    }
+
+    // A copy of `branch`, `height` levels above the chains, with `leaf` added
+    // as chain number `index`. Only the branches on the way down are copied.
+    fn push_leaf(branch: &[Slot<T>], height: u32, index: u64, leaf: Slot<T>) -> Arc<Vec<Slot<T>>> {
+        let at = (index >> (BITS * (height - 1))) as usize & (CHUNK - 1);
+        let mut copy = branch.to_vec();
+        match branch.get(at) {
+            Some(Slot::Branch(child)) => {
+                copy[at] = Slot::Branch(Self::push_leaf(child, height - 1, index, leaf));
+            }
+            _ => copy.push(Self::path(height - 1, leaf)),
+        }
+        Arc::new(copy)
+    }
+
+    // `leaf` under `height` new branches of one slot each.
+    fn path(height: u32, leaf: Slot<T>) -> Slot<T> {
+        (0..height).fold(leaf, |slot, _| Slot::Branch(Arc::new(vec![slot])))
+    }
+
+    pub fn peek_front(&self) -> Option<&T> {
+        self.front
+            .as_ref()
+            .or(self.back_first.as_ref())
+            .map(|node| &node.value)
+    }
+
+    pub fn peek_back(&self) -> Option<&T> {
+        self.tail
+            .as_ref()
+            .or(self.front_last.as_ref())
+            .map(|node| &node.value)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    /// Iterates from the front to the back.
+    ///
+    /// The chains at the back are newest first, so the iterator reverses them
+    /// one at a time: it never holds more than `CHUNK` entries and the path of
+    /// O(log n) branches down to the current chain, however the log was built.
+    pub fn iter(&self) -> ListIterator<'_, T> {
+        let mut path = Vec::with_capacity(self.height as usize);
+        path.extend(self.root.as_deref().map(|root| (root.as_slice(), 0)));
+        ListIterator {
+            next: self.front.as_deref(),
+            path,
+            tail: self.tail.as_deref(),
+            chunk: Vec::with_capacity(CHUNK),
+            length: self.length,
+        }
+    }
+}
+
+impl<T> Clone for PersistentTransactionLog<T> {
+    /// An O(1) snapshot: the copy shares every node.
+    fn clone(&self) -> Self {
+        Self {
+            front: self.front.clone(),
+            front_last: self.front_last.clone(),
+            root: self.root.clone(),
+            height: self.height,
+            leaves: self.leaves,
+            tail: self.tail.clone(),
+            tail_length: self.tail_length,
+            back_first: self.back_first.clone(),
+            length: self.length,
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for PersistentTransactionLog<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<T> FromIterator<T> for PersistentTransactionLog<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        iter.into_iter()
+            .fold(Self::new_empty(), |log, value| log.append(value))
+    }
+}
+
+impl<'a, T> IntoIterator for &'a PersistentTransactionLog<T> {
+    type Item = &'a T;
+    type IntoIter = ListIterator<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Borrowing iterator over a `PersistentTransactionLog`. Once it is through
+/// the front list it walks the trie down to each chain in turn, then the
+/// unsealed tail, and hands each chain out in reverse.
+pub struct ListIterator<'a, T> {
+    next: Option<&'a Node<T>>,
+    // the branches above the next chain, each with the index of its next slot
+    path: Vec<(&'a [Slot<T>], usize)>,
+    tail: Option<&'a Node<T>>,
+    // the rest of the current chain, newest first
+    chunk: Vec<&'a T>,
+    length: u64,
+}
+
+impl<'a, T> ListIterator<'a, T> {
+    // The next sealed chain in the trie, or the tail once they are all read.
+    fn next_chain(&mut self) -> Option<&'a Node<T>> {
+        while let Some((branch, at)) = self.path.last_mut() {
+            let branch: &'a [Slot<T>] = branch;
+            match branch.get(*at) {
+                Some(slot) => {
+                    *at += 1;
+                    match slot {
+                        Slot::Leaf(chain) => return Some(chain),
+                        Slot::Branch(child) => self.path.push((child, 0)),
+                    }
+                }
+                None => {
+                    self.path.pop();
+                }
+            }
+        }
+        self.tail.take()
+    }
+}
+
+impl<'a, T> Iterator for ListIterator<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(node) = self.next {
+            self.next = node.next.as_deref();
+            self.length -= 1;
+            return Some(&node.value);
+        }
+        if self.chunk.is_empty() {
+            let mut node = self.next_chain();
+            while let Some(current) = node {
+                self.chunk.push(&current.value);
+                node = current.next.as_deref();
+            }
+        }
+        let value = self.chunk.pop()?;
+        self.length -= 1;
+        Some(value)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.length as usize, Some(self.length as usize))
+    }
+}
+
+impl<T> ExactSizeIterator for ListIterator<'_, T> {}
+
+fn main() {
+    println!("Hara Hara Mahadev!!! Persistent Linked List");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{mpsc, Barrier};
+    use std::thread;
+
+    fn assert_log(log: &PersistentTransactionLog<u64>, expected: &[u64]) {
+        assert_eq!(log.iter().copied().collect::<Vec<_>>(), expected);
+        assert_eq!(log.iter().len(), expected.len());
+        assert_eq!(log.length, expected.len() as u64);
+        assert_eq!(log.peek_front(), expected.first());
+        assert_eq!(log.peek_back(), expected.last());
+    }
+
+    #[test]
+    fn persistent_transaction_log_append_push_front() {
+        let empty = PersistentTransactionLog::new_empty();
+        assert!(empty.is_empty());
+        let one = empty.append(1);
+        let two = one.append(2);
+        let zero_two = two.push_front(0);
+        let front_only = empty.push_front(5).push_front(4);
+        assert_log(&empty, &[]);
+        assert_log(&one, &[1]);
+        assert_log(&two, &[1, 2]);
+        assert_log(&zero_two, &[0, 1, 2]);
+        assert_log(&front_only, &[4, 5]);
+        assert_log(&front_only.append(6), &[4, 5, 6]);
+        assert_eq!(format!("{:?}", zero_two), "[0, 1, 2]");
+    }
+
+    #[test]
+    fn persistent_transaction_log_iter_across_chunks() {
+        let chunk = CHUNK as u64;
+        let sizes = [
+            1,
+            chunk - 1,
+            chunk,
+            chunk + 1,
+            chunk * chunk,
+            chunk * chunk + 1,
+        ];
+        for n in sizes.into_iter().chain([chunk * chunk * chunk + chunk + 3]) {
+            let log: PersistentTransactionLog<u64> = (1..=n).collect();
+            let expected: Vec<u64> = (1..=n).collect();
+            assert_log(&log, &expected);
+            let mut iter = log.iter();
+            assert_eq!(iter.next(), Some(&1));
+            assert_eq!(iter.len() as u64, n - 1);
+            // versions built on the snapshot leave it as it was
+            let longer = log.append(n + 1).push_front(0);
+            assert_log(&log, &expected);
+            let expected: Vec<u64> = (0..=n + 1).collect();
+            assert_log(&longer, &expected);
+        }
+    }
+
+    #[test]
+    fn persistent_transaction_log_versions_share_nodes() {
+        let base: PersistentTransactionLog<u64> = (0..3).collect();
+        let left = base.append(10);
+        let right = base.append(20);
+        assert_log(&left, &[0, 1, 2, 10]);
+        assert_log(&right, &[0, 1, 2, 20]);
+        let shared = |log: &PersistentTransactionLog<u64>| {
+            log.tail.as_ref().unwrap().next.as_ref().unwrap().clone()
+        };
+        assert!(Arc::ptr_eq(&shared(&left), base.tail.as_ref().unwrap()));
+        assert!(Arc::ptr_eq(&shared(&left), &shared(&right)));
+        // versions past the first sealed chain share the trie as well
+        let base: PersistentTransactionLog<u64> = (0..100).collect();
+        let (left, right) = (base.append(100), base.append(200));
+        assert!(Arc::ptr_eq(
+            left.root.as_ref().unwrap(),
+            right.root.as_ref().unwrap()
+        ));
+        // dropping a version leaves the nodes the others still use
+        drop(base);
+        drop(left);
+        let expected: Vec<u64> = (0..100).chain([200]).collect();
+        assert_log(&right, &expected);
+    }
+
+    #[test]
+    fn persistent_transaction_log_snapshots_across_threads() {
+        let (sender, receiver) = mpsc::channel();
+        let writer = thread::spawn(move || {
+            let mut log = PersistentTransactionLog::new_empty();
+            for i in 0..1000 {
+                log = log.append(i);
+                if i % 100 == 99 {
+                    sender.send(log.clone()).unwrap();
+                }
+            }
+        });
+        let mut seen = 0;
+        for snapshot in receiver {
+            seen += 1;
+            let expected: Vec<u64> = (0..seen * 100).collect();
+            assert_log(&snapshot, &expected);
+        }
+        writer.join().unwrap();
+        assert_eq!(seen, 10);
+    }
+
+    #[test]
+    fn persistent_transaction_log_drop_long_log() {
+        let log: PersistentTransactionLog<u64> = (0..1_000_000).collect();
+        let snapshot = log.clone();
+        let longer = log.push_front(0);
+        drop(log);
+        drop(longer);
+        assert_eq!(snapshot.length, 1_000_000);
+        drop(snapshot);
+    }
+
+    #[test]
+    fn persistent_transaction_log_drop_shared_suffix_across_threads() {
+        for _ in 0..4 {
+            let base = (0..1_000_000).fold(PersistentTransactionLog::new_empty(), |log, i| {
+                log.push_front(i)
+            });
+            let versions = [base.push_front(1), base.push_front(2)];
+            drop(base);
+            // both threads let go of the shared million nodes at the same time
+            let barrier = Arc::new(Barrier::new(2));
+            let droppers: Vec<_> = versions
+                .into_iter()
+                .map(|version| {
+                    let barrier = barrier.clone();
+                    thread::spawn(move || {
+                        barrier.wait();
+                        drop(version);
+                    })
+                })
+                .collect();
+            for dropper in droppers {
+                dropper.join().unwrap();
+            }
+        }
+    }
+}
@@ -0,0 +1,1013 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Sender},
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime},
+};
+
+// only the queue operations of `TransactionLog` are used here
+#[allow(dead_code)]
+#[path = "c04p01_singly_linked_list/transaction_log.rs"]
+mod transaction_log;
+
+use transaction_log::TransactionLog;
+
+/// An entry type that a `DurableTransactionLog` can write to disk.
+pub trait Record: Sized {
+    fn encode(&self, buf: &mut Vec<u8>);
+    fn decode(bytes: &[u8]) -> Option<Self>;
+}
+
+impl Record for String {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self.as_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        String::from_utf8(bytes.to_vec()).ok()
+    }
+}
+
+impl Record for Vec<u8> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self);
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        Some(bytes.to_vec())
+    }
+}
+
+impl Record for u64 {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.to_le_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        bytes.try_into().ok().map(u64::from_le_bytes)
+    }
+}
+
+/// When a `DurableTransactionLog` forces its writes to stable storage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    /// fsync after every append and pop
+    Always,
+    /// fsync after this many appends and pops
+    Batch(u64),
+    /// leave flushing to the operating system
+    Never,
+}
+
+// every record is [crc32 of the rest: u32][payload length: u32][payload]; the
+// checksum covers the length too, so a zero-filled tail doesn't pass for empty
+// records
+const RECORD_HEADER: usize = 8;
+
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 == 1 {
+                0xedb8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0, |c, &b| {
+        CRC32_TABLE[((c ^ b as u32) & 0xff) as usize] ^ (c >> 8)
+    })
+}
+
+fn encode_record<T: Record>(value: &T) -> io::Result<Vec<u8>> {
+    let mut record = vec![0; RECORD_HEADER];
+    value.encode(&mut record);
+    let len = u32::try_from(record.len() - RECORD_HEADER)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "record is too large"))?;
+    record[4..RECORD_HEADER].copy_from_slice(&len.to_le_bytes());
+    let crc = crc32(&record[4..]);
+    record[..4].copy_from_slice(&crc.to_le_bytes());
+    Ok(record)
+}
+
+// Returns the payload of the record starting at `pos` and the position right
+// after it, or `None` if the record is cut short or fails its checksum.
+fn read_record(bytes: &[u8], pos: usize) -> Option<(&[u8], usize)> {
+    let header = bytes.get(pos..pos + RECORD_HEADER)?;
+    let crc = u32::from_le_bytes(header[..4].try_into().unwrap());
+    let len = u32::from_le_bytes(header[4..].try_into().unwrap()) as usize;
+    let start = pos + RECORD_HEADER;
+    let checked = bytes.get(pos + 4..start + len)?;
+    if crc32(checked) == crc {
+        Some((&checked[4..], start + len))
+    } else {
+        None
+    }
+}
+
+// The consumer offset is stored as [offset: u64][crc32 of the offset: u32];
+// a missing or damaged offset file means replaying from the start.
+fn read_consumer_offset(dir: &Path) -> io::Result<u64> {
+    let bytes = match fs::read(dir.join("consumer.offset")) {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err),
+    };
+    match bytes.get(..12) {
+        Some(bytes) if crc32(&bytes[..8]) == u32::from_le_bytes(bytes[8..].try_into().unwrap()) => {
+            Ok(u64::from_le_bytes(bytes[..8].try_into().unwrap()))
+        }
+        _ => Ok(0),
+    }
+}
+
+// Writes the offset to a temporary file and renames it over the old one, so a
+// crash leaves either the old or the new offset behind and never a torn one.
+// With `sync` the new file reaches the disk before the rename does.
+fn write_consumer_offset(dir: &Path, offset: u64, sync: bool) -> io::Result<()> {
+    let mut bytes = [0; 12];
+    bytes[..8].copy_from_slice(&offset.to_le_bytes());
+    let crc = crc32(&bytes[..8]);
+    bytes[8..].copy_from_slice(&crc.to_le_bytes());
+    let temp = dir.join("consumer.offset.tmp");
+    let mut file = File::create(&temp)?;
+    file.write_all(&bytes)?;
+    if sync {
+        file.sync_all()?;
+    }
+    fs::rename(temp, dir.join("consumer.offset"))
+}
+
+// Files created, removed or renamed in `dir` only survive a crash once the
+// directory itself is synced.
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+/// How much of a `DurableTransactionLog` is kept on disk. Retention only
+/// ever removes sealed segments, never the one being appended to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Retention {
+    pub max_segments: Option<usize>,
+    pub max_bytes: Option<u64>,
+    pub max_age: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogOptions {
+    pub fsync: FsyncPolicy,
+    /// a segment is sealed once the next record would grow it past this
+    pub segment_bytes: u64,
+    pub retention: Retention,
+}
+
+impl Default for LogOptions {
+    fn default() -> Self {
+        Self {
+            fsync: FsyncPolicy::Always,
+            segment_bytes: 64 * 1024 * 1024,
+            retention: Retention::default(),
+        }
+    }
+}
+
+// every index entry is [logical offset: u64][byte position in the segment: u64]
+const INDEX_ENTRY: usize = 16;
+
+struct Segment {
+    // logical offset of the first record
+    base: u64,
+    // logical offset right after the last record
+    next: u64,
+    bytes: u64,
+    modified: SystemTime,
+}
+
+fn segment_path(dir: &Path, base: u64, extension: &str) -> PathBuf {
+    dir.join(format!("{:020}.{}", base, extension))
+}
+
+// Returns the position of every intact record and where the intact part ends.
+fn scan_records(bytes: &[u8]) -> (Vec<u64>, usize) {
+    let mut positions = Vec::new();
+    let mut pos = 0;
+    while let Some((_, next)) = read_record(bytes, pos) {
+        positions.push(pos as u64);
+        pos = next;
+    }
+    (positions, pos)
+}
+
+fn encode_index(base: u64, positions: &[u64]) -> Vec<u8> {
+    let mut index = Vec::with_capacity(positions.len() * INDEX_ENTRY);
+    for (i, pos) in positions.iter().enumerate() {
+        index.extend_from_slice(&(base + i as u64).to_le_bytes());
+        index.extend_from_slice(&pos.to_le_bytes());
+    }
+    index
+}
+
+fn index_lookup(index: &[u8], base: u64, offset: u64) -> Option<u64> {
+    let at = usize::try_from(offset.checked_sub(base)?).ok()? * INDEX_ENTRY;
+    let entry = index.get(at..at + INDEX_ENTRY)?;
+    if u64::from_le_bytes(entry[..8].try_into().unwrap()) == offset {
+        Some(u64::from_le_bytes(entry[8..].try_into().unwrap()))
+    } else {
+        None
+    }
+}
+
+// A sealed segment's index can be trusted without rescanning the log if its
+// entries number the offsets from `base` in order, their positions start at 0
+// and rise, and the last one points at a valid record that ends the log.
+fn index_is_intact(index: &[u8], base: u64, mut log: &File, bytes: u64) -> io::Result<bool> {
+    if index.is_empty() || !index.len().is_multiple_of(INDEX_ENTRY) {
+        return Ok(false);
+    }
+    let mut last = None;
+    for (i, entry) in index.chunks_exact(INDEX_ENTRY).enumerate() {
+        let offset = u64::from_le_bytes(entry[..8].try_into().unwrap());
+        let pos = u64::from_le_bytes(entry[8..].try_into().unwrap());
+        let in_order = match last {
+            None => pos == 0,
+            Some(last) => pos > last,
+        };
+        if offset != base + i as u64 || !in_order || pos >= bytes {
+            return Ok(false);
+        }
+        last = Some(pos);
+    }
+    let mut tail = Vec::new();
+    log.seek(SeekFrom::Start(last.unwrap()))?;
+    log.read_to_end(&mut tail)?;
+    Ok(read_record(&tail, 0).is_some_and(|(_, end)| end == tail.len()))
+}
+
+// Loads a segment and its index. The active segment is always rescanned
+// because a crash can leave a torn record or index entry at its end.
+fn load_segment(dir: &Path, base: u64, active: bool) -> io::Result<(Segment, Vec<u8>)> {
+    let log_path = segment_path(dir, base, "log");
+    let index_path = segment_path(dir, base, "index");
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&log_path)?;
+    let metadata = file.metadata()?;
+    let mut bytes = metadata.len();
+    let mut index = fs::read(&index_path).unwrap_or_default();
+    if active || !index_is_intact(&index, base, &file, bytes)? {
+        let (positions, end) = scan_records(&fs::read(&log_path)?);
+        if (end as u64) < bytes {
+            // a torn or corrupted tail left behind by a crash
+            file.set_len(end as u64)?;
+            file.sync_all()?;
+            bytes = end as u64;
+        }
+        let rebuilt = encode_index(base, &positions);
+        if rebuilt != index {
+            fs::write(&index_path, &rebuilt)?;
+            index = rebuilt;
+        }
+    }
+    let segment = Segment {
+        base,
+        next: base + (index.len() / INDEX_ENTRY) as u64,
+        bytes,
+        modified: metadata.modified()?,
+    };
+    Ok((segment, index))
+}
+
+/// A `TransactionLog` journaled to a directory of write-ahead log segments.
+///
+/// Appends go to the newest segment as checksummed records, each with an
+/// entry in the segment's index. `pop` only advances the consumer offset
+/// stored in `consumer.offset`, so opening the directory again replays every
+/// entry that hasn't been popped yet. Segments the consumer has moved past,
+/// or that fall outside the `Retention`, are deleted by a background thread.
+pub struct DurableTransactionLog<T> {
+    // every entry remembers its logical offset
+    log: TransactionLog<(u64, T)>,
+    dir: PathBuf,
+    options: LogOptions,
+    segments: Vec<Segment>,
+    active_log: File,
+    active_index: File,
+    consumer: u64,
+    unsynced: u64,
+    deleter: Option<Sender<u64>>,
+    deleter_thread: Option<JoinHandle<()>>,
+}
+
+impl<T: Record> DurableTransactionLog<T> {
+    pub fn open(dir: impl AsRef<Path>, options: LogOptions) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let mut bases = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension() == Some("log".as_ref()) {
+                if let Some(base) = path.file_stem().and_then(|s| s.to_str()?.parse().ok()) {
+                    bases.push(base);
+                }
+            }
+        }
+        bases.sort_unstable();
+        if bases.is_empty() {
+            bases.push(0);
+        }
+
+        let mut segments = Vec::new();
+        let mut indexes = Vec::new();
+        for (i, &base) in bases.iter().enumerate() {
+            let (segment, index) = load_segment(&dir, base, i == bases.len() - 1)?;
+            segments.push(segment);
+            indexes.push(index);
+        }
+
+        let stored = read_consumer_offset(&dir)?;
+        // retention may have removed entries nobody popped, and a torn tail
+        // may have removed entries that were already popped
+        let consumer = stored
+            .max(segments[0].base)
+            .min(segments[segments.len() - 1].next);
+        if consumer != stored {
+            write_consumer_offset(&dir, consumer, true)?;
+        }
+
+        let mut log = TransactionLog::new_empty();
+        for (segment, index) in segments.iter().zip(&indexes) {
+            if segment.next <= consumer {
+                continue;
+            }
+            let mut offset = consumer.max(segment.base);
+            let start = index_lookup(index, segment.base, offset).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("offset {} is missing from its index", offset),
+                )
+            })?;
+            let mut file = File::open(segment_path(&dir, segment.base, "log"))?;
+            file.seek(SeekFrom::Start(start))?;
+            let mut bytes = Vec::new();
+            file.read_to_end(&mut bytes)?;
+            let mut pos = 0;
+            while offset < segment.next {
+                let value = read_record(&bytes, pos)
+                    .and_then(|(payload, next)| {
+                        pos = next;
+                        T::decode(payload)
+                    })
+                    .ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("record {} is corrupted", offset),
+                        )
+                    })?;
+                log.append((offset, value));
+                offset += 1;
+            }
+        }
+
+        let active = segments[segments.len() - 1].base;
+        let open = |extension| {
+            OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(segment_path(&dir, active, extension))
+        };
+        let active_log = open("log")?;
+        let active_index = open("index")?;
+        if options.fsync != FsyncPolicy::Never {
+            // the first segment may have just been created
+            sync_dir(&dir)?;
+        }
+
+        let (deleter, removed) = mpsc::channel::<u64>();
+        let deleter_dir = dir.clone();
+        let deleter_thread = thread::spawn(move || {
+            for base in removed {
+                let _ = fs::remove_file(segment_path(&deleter_dir, base, "log"));
+                let _ = fs::remove_file(segment_path(&deleter_dir, base, "index"));
+            }
+        });
+
+        let mut durable = Self {
+            log,
+            dir,
+            options,
+            segments,
+            active_log,
+            active_index,
+            consumer,
+            unsynced: 0,
+            deleter: Some(deleter),
+            deleter_thread: Some(deleter_thread),
+        };
+        durable.remove_consumed();
+        durable.enforce_retention(0)?;
+        Ok(durable)
+    }
+
+    /// Appends `value` and writes it to the active segment. On an error
+    /// nothing is appended, so the call can simply be retried.
+    pub fn append(&mut self, value: T) -> io::Result<()> {
+        let record = encode_record(&value)?;
+        let active = &self.segments[self.segments.len() - 1];
+        if active.bytes > 0 && active.bytes + record.len() as u64 > self.options.segment_bytes {
+            self.roll()?;
+        }
+        // retention counts the new record but runs before it is written, so
+        // every step that can fail comes before the append takes effect
+        self.enforce_retention(record.len() as u64)?;
+        let active = &self.segments[self.segments.len() - 1];
+        let (base, offset, bytes) = (active.base, active.next, active.bytes);
+        let written = self
+            .active_log
+            .write_all(&record)
+            .and_then(|()| self.active_index.write_all(&encode_index(offset, &[bytes])))
+            .and_then(|()| self.written());
+        if let Err(err) = written {
+            // take the record back out, so the log and its index agree and
+            // retrying the append doesn't write it twice
+            let _ = self.active_log.set_len(bytes);
+            let _ = self
+                .active_index
+                .set_len((offset - base) * INDEX_ENTRY as u64);
+            return Err(err);
+        }
+        let active = self.segments.last_mut().unwrap();
+        active.next += 1;
+        active.bytes += record.len() as u64;
+        active.modified = SystemTime::now();
+        self.log.append((offset, value));
+        Ok(())
+    }
+
+    /// Takes the oldest entry that hasn't been popped yet. On an error the
+    /// entry stays where it was and the next `pop` returns it again.
+    pub fn pop(&mut self) -> io::Result<Option<T>> {
+        let Some(&(offset, _)) = self.log.peek_front() else {
+            return Ok(None);
+        };
+        // the entry leaves memory only once the new offset is in place
+        self.set_consumer(offset + 1)?;
+        let (_, value) = self.log.pop().expect("the front entry was just peeked");
+        self.remove_consumed();
+        // The pop has happened either way. If syncing fails, `unsynced`
+        // stays up, so the next append, pop or `sync` tries again and reports
+        // the error before it changes anything.
+        let _ = self.written();
+        Ok(Some(value))
+    }
+
+    pub fn length(&self) -> u64 {
+        self.log.length
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.log.iter().map(|(_, value)| value)
+    }
+
+    /// Forces every append and pop so far to stable storage.
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.active_log.sync_data()?;
+        self.active_index.sync_data()?;
+        sync_dir(&self.dir)?;
+        self.unsynced = 0;
+        Ok(())
+    }
+
+    fn written(&mut self) -> io::Result<()> {
+        self.unsynced += 1;
+        match self.options.fsync {
+            FsyncPolicy::Always => self.sync(),
+            FsyncPolicy::Batch(n) if self.unsynced >= n => self.sync(),
+            _ => Ok(()),
+        }
+    }
+
+    // seals the active segment and starts a new one
+    fn roll(&mut self) -> io::Result<()> {
+        if self.options.fsync != FsyncPolicy::Never {
+            self.active_log.sync_data()?;
+            self.active_index.sync_data()?;
+        }
+        let base = self.segments[self.segments.len() - 1].next;
+        let open = |extension| {
+            OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(segment_path(&self.dir, base, extension))
+        };
+        self.active_log = open("log")?;
+        self.active_index = open("index")?;
+        if self.options.fsync != FsyncPolicy::Never {
+            sync_dir(&self.dir)?;
+        }
+        self.segments.push(Segment {
+            base,
+            next: base,
+            bytes: 0,
+            modified: SystemTime::now(),
+        });
+        Ok(())
+    }
+
+    fn set_consumer(&mut self, consumer: u64) -> io::Result<()> {
+        let sync = self.options.fsync != FsyncPolicy::Never;
+        write_consumer_offset(&self.dir, consumer, sync)?;
+        self.consumer = consumer;
+        Ok(())
+    }
+
+    fn remove_consumed(&mut self) {
+        while self.segments.len() > 1 && self.segments[0].next <= self.consumer {
+            self.remove_oldest();
+        }
+    }
+
+    // `pending` bytes are about to be appended to the active segment.
+    fn enforce_retention(&mut self, pending: u64) -> io::Result<()> {
+        let retention = self.options.retention;
+        while self.segments.len() > 1 {
+            let total = pending
+                + self
+                    .segments
+                    .iter()
+                    .map(|segment| segment.bytes)
+                    .sum::<u64>();
+            let oldest = &self.segments[0];
+            let expired = retention
+                .max_segments
+                .is_some_and(|max| self.segments.len() > max)
+                || retention.max_bytes.is_some_and(|max| total > max)
+                || retention
+                    .max_age
+                    .is_some_and(|max| oldest.modified.elapsed().is_ok_and(|age| age > max));
+            if !expired {
+                break;
+            }
+            // entries of an expired segment go even if nobody popped them,
+            // but only once the consumer offset has moved past them
+            let next = oldest.next;
+            if self.consumer < next {
+                self.set_consumer(next)?;
+            }
+            while self
+                .log
+                .peek_front()
+                .is_some_and(|(offset, _)| *offset < next)
+            {
+                self.log.pop();
+            }
+            self.remove_oldest();
+        }
+        Ok(())
+    }
+
+    fn remove_oldest(&mut self) {
+        let segment = self.segments.remove(0);
+        if let Some(deleter) = &self.deleter {
+            let _ = deleter.send(segment.base);
+        }
+    }
+}
+
+impl<T> Drop for DurableTransactionLog<T> {
+    fn drop(&mut self) {
+        // let the deleter finish so the directory can safely be reopened
+        self.deleter.take();
+        if let Some(deleter_thread) = self.deleter_thread.take() {
+            let _ = deleter_thread.join();
+        }
+    }
+}
+
+fn main() {
+    println!("Hara Hara Mahadev!!! Write-Ahead Log");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wal_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("c04p07-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn segment_bases(dir: &Path) -> Vec<u64> {
+        let mut bases: Vec<u64> = fs::read_dir(dir)
+            .unwrap()
+            .filter_map(|entry| {
+                let path = entry.unwrap().path();
+                match path.extension() {
+                    Some(extension) if extension == "log" => {
+                        path.file_stem()?.to_str()?.parse().ok()
+                    }
+                    _ => None,
+                }
+            })
+            .collect();
+        bases.sort_unstable();
+        bases
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn durable_transaction_log_replay() {
+        let dir = wal_dir("replay");
+        {
+            let mut log = DurableTransactionLog::open(&dir, LogOptions::default()).unwrap();
+            assert_eq!(log.length(), 0);
+            log.append("Siva".to_owned()).unwrap();
+            log.append("Krishna".to_owned()).unwrap();
+            log.append("Shambho".to_owned()).unwrap();
+        }
+        let path = segment_path(&dir, 0, "log");
+        let size = fs::metadata(&path).unwrap().len();
+        let options = LogOptions {
+            fsync: FsyncPolicy::Batch(2),
+            ..LogOptions::default()
+        };
+        {
+            let mut log: DurableTransactionLog<String> =
+                DurableTransactionLog::open(&dir, options).unwrap();
+            assert_eq!(log.length(), 3);
+            assert_eq!(log.pop().unwrap(), Some("Siva".to_owned()));
+        }
+        // popping moves the consumer offset, the log itself is untouched
+        assert_eq!(fs::metadata(&path).unwrap().len(), size);
+        let options = LogOptions {
+            fsync: FsyncPolicy::Never,
+            ..LogOptions::default()
+        };
+        {
+            let mut log = DurableTransactionLog::open(&dir, options).unwrap();
+            assert_eq!(
+                log.iter().cloned().collect::<Vec<String>>(),
+                vec!["Krishna".to_owned(), "Shambho".to_owned()]
+            );
+            assert_eq!(log.pop().unwrap(), Some("Krishna".to_owned()));
+            assert_eq!(log.pop().unwrap(), Some("Shambho".to_owned()));
+            assert_eq!(log.pop().unwrap(), None);
+            log.append("Shankara".to_owned()).unwrap();
+        }
+        let mut log = DurableTransactionLog::open(&dir, LogOptions::default()).unwrap();
+        assert_eq!(log.length(), 1);
+        assert_eq!(log.pop().unwrap(), Some("Shankara".to_owned()));
+        drop(log);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn durable_transaction_log_consumer_offset_is_replaced() {
+        let dir = wal_dir("consumer");
+        let options = LogOptions {
+            fsync: FsyncPolicy::Always,
+            ..LogOptions::default()
+        };
+        {
+            let mut log = DurableTransactionLog::open(&dir, options).unwrap();
+            for i in 0..4u64 {
+                log.append(i).unwrap();
+            }
+            assert_eq!(log.pop().unwrap(), Some(0));
+            assert_eq!(log.pop().unwrap(), Some(1));
+        }
+        assert!(!dir.join("consumer.offset.tmp").exists());
+        // a crash while the next offset was being written only tears the
+        // temporary file
+        fs::write(dir.join("consumer.offset.tmp"), [3, 0, 0]).unwrap();
+        {
+            let mut log: DurableTransactionLog<u64> =
+                DurableTransactionLog::open(&dir, options).unwrap();
+            assert_eq!(log.iter().copied().collect::<Vec<_>>(), vec![2, 3]);
+            assert_eq!(log.pop().unwrap(), Some(2));
+        }
+        let log: DurableTransactionLog<u64> = DurableTransactionLog::open(&dir, options).unwrap();
+        assert_eq!(log.iter().copied().collect::<Vec<_>>(), vec![3]);
+        drop(log);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn durable_transaction_log_torn_tail() {
+        let dir = wal_dir("torn");
+        let path = segment_path(&dir, 0, "log");
+        {
+            let mut log = DurableTransactionLog::open(&dir, LogOptions::default()).unwrap();
+            for i in 0..3u64 {
+                log.append(i).unwrap();
+            }
+        }
+        // a crash in the middle of the last write
+        let size = fs::metadata(&path).unwrap().len();
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(size - 3).unwrap();
+        drop(file);
+        {
+            let mut log: DurableTransactionLog<u64> =
+                DurableTransactionLog::open(&dir, LogOptions::default()).unwrap();
+            assert_eq!(log.length(), 2);
+            assert_eq!(fs::metadata(&path).unwrap().len(), size - 16);
+            log.append(3).unwrap();
+        }
+        {
+            let mut log: DurableTransactionLog<u64> =
+                DurableTransactionLog::open(&dir, LogOptions::default()).unwrap();
+            assert_eq!(log.iter().copied().collect::<Vec<_>>(), vec![0, 1, 3]);
+            while log.pop().unwrap().is_some() {}
+        }
+        // the consumer offset never points past the end of a torn log
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(size - 3).unwrap();
+        drop(file);
+        {
+            let mut log: DurableTransactionLog<u64> =
+                DurableTransactionLog::open(&dir, LogOptions::default()).unwrap();
+            assert_eq!(log.length(), 0);
+            log.append(4).unwrap();
+        }
+        let log: DurableTransactionLog<u64> =
+            DurableTransactionLog::open(&dir, LogOptions::default()).unwrap();
+        assert_eq!(log.iter().copied().collect::<Vec<_>>(), vec![4]);
+        drop(log);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn durable_transaction_log_zero_filled_tail() {
+        let dir = wal_dir("zeros");
+        let path = segment_path(&dir, 0, "log");
+        {
+            let mut log = DurableTransactionLog::open(&dir, LogOptions::default()).unwrap();
+            log.append("Siva".to_owned()).unwrap();
+        }
+        // a crash after the file grew but before the data reached the disk
+        let size = fs::metadata(&path).unwrap().len();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[0; 24]).unwrap();
+        drop(file);
+        {
+            let mut log: DurableTransactionLog<String> =
+                DurableTransactionLog::open(&dir, LogOptions::default()).unwrap();
+            assert_eq!(log.iter().collect::<Vec<_>>(), vec!["Siva"]);
+            assert_eq!(fs::metadata(&path).unwrap().len(), size);
+            log.append("Shambho".to_owned()).unwrap();
+        }
+        let log: DurableTransactionLog<String> =
+            DurableTransactionLog::open(&dir, LogOptions::default()).unwrap();
+        assert_eq!(log.iter().collect::<Vec<_>>(), vec!["Siva", "Shambho"]);
+        drop(log);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn durable_transaction_log_corrupt_tail() {
+        let dir = wal_dir("corrupt");
+        let options = LogOptions {
+            fsync: FsyncPolicy::Never,
+            ..LogOptions::default()
+        };
+        {
+            let mut log = DurableTransactionLog::open(&dir, options).unwrap();
+            log.append(b"first".to_vec()).unwrap();
+            log.append(b"second".to_vec()).unwrap();
+        }
+        let path = segment_path(&dir, 0, "log");
+        let mut bytes = fs::read(&path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        fs::write(&path, bytes).unwrap();
+        let mut log: DurableTransactionLog<Vec<u8>> =
+            DurableTransactionLog::open(&dir, options).unwrap();
+        assert_eq!(log.length(), 1);
+        assert_eq!(log.pop().unwrap(), Some(b"first".to_vec()));
+        drop(log);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    // every u64 record takes 16 bytes, so four of them fill a segment
+    const SEGMENT_OPTIONS: LogOptions = LogOptions {
+        fsync: FsyncPolicy::Never,
+        segment_bytes: 64,
+        retention: Retention {
+            max_segments: None,
+            max_bytes: None,
+            max_age: None,
+        },
+    };
+
+    #[test]
+    fn durable_transaction_log_segments() {
+        let dir = wal_dir("segments");
+        {
+            let mut log = DurableTransactionLog::open(&dir, SEGMENT_OPTIONS).unwrap();
+            for i in 0..10u64 {
+                log.append(i * 100).unwrap();
+            }
+        }
+        assert_eq!(segment_bases(&dir), vec![0, 4, 8]);
+        let index = fs::read(segment_path(&dir, 4, "index")).unwrap();
+        assert_eq!(index, encode_index(4, &[0, 16, 32, 48]));
+        assert_eq!(index_lookup(&index, 4, 6), Some(32));
+        {
+            // replay starts in the middle of a segment
+            let mut log: DurableTransactionLog<u64> =
+                DurableTransactionLog::open(&dir, SEGMENT_OPTIONS).unwrap();
+            for i in 0..6u64 {
+                assert_eq!(log.pop().unwrap(), Some(i * 100));
+            }
+        }
+        // the first segment was consumed and removed in the background
+        assert_eq!(segment_bases(&dir), vec![4, 8]);
+        let mut log: DurableTransactionLog<u64> =
+            DurableTransactionLog::open(&dir, SEGMENT_OPTIONS).unwrap();
+        assert_eq!(
+            log.iter().copied().collect::<Vec<_>>(),
+            vec![600, 700, 800, 900]
+        );
+        log.append(1000).unwrap();
+        while log.pop().unwrap().is_some() {}
+        drop(log);
+        // the active segment stays even when fully consumed
+        assert_eq!(segment_bases(&dir), vec![8]);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn durable_transaction_log_rebuilds_index() {
+        let dir = wal_dir("index");
+        {
+            let mut log = DurableTransactionLog::open(&dir, SEGMENT_OPTIONS).unwrap();
+            for i in 0..6u64 {
+                log.append(i).unwrap();
+            }
+            log.pop().unwrap();
+            log.pop().unwrap();
+        }
+        fs::remove_file(segment_path(&dir, 0, "index")).unwrap();
+        fs::write(segment_path(&dir, 4, "index"), [1, 2, 3]).unwrap();
+        let log: DurableTransactionLog<u64> =
+            DurableTransactionLog::open(&dir, SEGMENT_OPTIONS).unwrap();
+        assert_eq!(log.iter().copied().collect::<Vec<_>>(), vec![2, 3, 4, 5]);
+        assert_eq!(
+            fs::read(segment_path(&dir, 4, "index")).unwrap(),
+            encode_index(4, &[0, 16])
+        );
+        drop(log);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn durable_transaction_log_checks_sealed_index() {
+        let dir = wal_dir("sealed-index");
+        {
+            let mut log = DurableTransactionLog::open(&dir, SEGMENT_OPTIONS).unwrap();
+            for i in 0..10u64 {
+                log.append(i).unwrap();
+            }
+        }
+        // the last entry still points at the last record, but the middle two
+        // are swapped
+        let index_path = segment_path(&dir, 0, "index");
+        fs::write(&index_path, encode_index(0, &[0, 32, 16, 48])).unwrap();
+        let log: DurableTransactionLog<u64> =
+            DurableTransactionLog::open(&dir, SEGMENT_OPTIONS).unwrap();
+        assert_eq!(
+            log.iter().copied().collect::<Vec<_>>(),
+            (0..10).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            fs::read(&index_path).unwrap(),
+            encode_index(0, &[0, 16, 32, 48])
+        );
+        drop(log);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn durable_transaction_log_errors_change_nothing() {
+        let dir = wal_dir("errors");
+        let options = LogOptions {
+            retention: Retention {
+                max_segments: Some(2),
+                ..Retention::default()
+            },
+            ..SEGMENT_OPTIONS
+        };
+        let mut log = DurableTransactionLog::open(&dir, options).unwrap();
+        for i in 0..8u64 {
+            log.append(i).unwrap();
+        }
+        // a directory in the way makes every consumer offset write fail
+        let blocker = dir.join("consumer.offset.tmp");
+        fs::create_dir(&blocker).unwrap();
+        assert!(log.pop().is_err());
+        // the third segment pushes out the first, which moves the consumer
+        assert!(log.append(8).is_err());
+        assert_eq!(
+            log.iter().copied().collect::<Vec<_>>(),
+            (0..8).collect::<Vec<_>>()
+        );
+        fs::remove_dir(&blocker).unwrap();
+        log.append(8).unwrap();
+        assert_eq!(log.pop().unwrap(), Some(4));
+        assert_eq!(log.iter().copied().collect::<Vec<_>>(), vec![5, 6, 7, 8]);
+        drop(log);
+        let log: DurableTransactionLog<u64> = DurableTransactionLog::open(&dir, options).unwrap();
+        assert_eq!(log.iter().copied().collect::<Vec<_>>(), vec![5, 6, 7, 8]);
+        drop(log);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn durable_transaction_log_retention() {
+        let dir = wal_dir("retention-count");
+        let options = LogOptions {
+            retention: Retention {
+                max_segments: Some(2),
+                ..Retention::default()
+            },
+            ..SEGMENT_OPTIONS
+        };
+        let mut log = DurableTransactionLog::open(&dir, options).unwrap();
+        for i in 0..10u64 {
+            log.append(i).unwrap();
+        }
+        assert_eq!(log.length(), 6);
+        assert_eq!(log.pop().unwrap(), Some(4));
+        drop(log);
+        assert_eq!(segment_bases(&dir), vec![4, 8]);
+        let _ = fs::remove_dir_all(dir);
+
+        let dir = wal_dir("retention-bytes");
+        let options = LogOptions {
+            retention: Retention {
+                max_bytes: Some(100),
+                ..Retention::default()
+            },
+            ..SEGMENT_OPTIONS
+        };
+        let mut log = DurableTransactionLog::open(&dir, options).unwrap();
+        for i in 0..10u64 {
+            log.append(i).unwrap();
+        }
+        // 64 + 32 bytes fit, a third segment would not
+        assert_eq!(
+            log.iter().copied().collect::<Vec<_>>(),
+            vec![4, 5, 6, 7, 8, 9]
+        );
+        drop(log);
+        // unpopped entries dropped by retention aren't replayed
+        let log: DurableTransactionLog<u64> = DurableTransactionLog::open(&dir, options).unwrap();
+        assert_eq!(log.length(), 6);
+        drop(log);
+        let _ = fs::remove_dir_all(dir);
+
+        let dir = wal_dir("retention-age");
+        let mut log = DurableTransactionLog::open(&dir, SEGMENT_OPTIONS).unwrap();
+        for i in 0..10u64 {
+            log.append(i).unwrap();
+        }
+        drop(log);
+        let options = LogOptions {
+            retention: Retention {
+                max_age: Some(Duration::ZERO),
+                ..Retention::default()
+            },
+            ..SEGMENT_OPTIONS
+        };
+        std::thread::sleep(Duration::from_millis(10));
+        let log: DurableTransactionLog<u64> = DurableTransactionLog::open(&dir, options).unwrap();
+        assert_eq!(log.iter().copied().collect::<Vec<_>>(), vec![8, 9]);
+        drop(log);
+        assert_eq!(segment_bases(&dir), vec![8]);
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,171 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Condvar, Mutex,
+};
+
+// only the queue operations of `TransactionLog` are used here
+#[allow(dead_code)]
+#[path = "c04p01_singly_linked_list/transaction_log.rs"]
+mod transaction_log;
+
+use transaction_log::TransactionLog;
+
+/// What a `BoundedTransactionLog` does with an append when it is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backpressure {
+    /// hand the entry back to the caller
+    Reject,
+    /// drop the oldest entry to make room
+    EvictOldest,
+    /// wait until a consumer pops an entry
+    Block,
+}
+
+/// The entry a full `BoundedTransactionLog` rejected.
+#[derive(Debug, PartialEq, Eq)]
+pub struct LogFull<T>(pub T);
+
+/// A `TransactionLog` that holds at most `capacity` entries and can be
+/// shared between producer and consumer threads.
+pub struct BoundedTransactionLog<T> {
+    log: Mutex<TransactionLog<T>>,
+    not_full: Condvar,
+    capacity: u64,
+    policy: Backpressure,
+    evicted: AtomicU64,
+    rejected: AtomicU64,
+}
+
+impl<T> BoundedTransactionLog<T> {
+    pub fn new_empty(capacity: u64, policy: Backpressure) -> Self {
+        assert!(capacity > 0, "a bounded log needs room for one entry");
+        Self {
+            log: Mutex::new(TransactionLog::new_empty()),
+            not_full: Condvar::new(),
+            capacity,
+            policy,
+            evicted: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    pub fn append(&self, value: T) -> Result<(), LogFull<T>> {
+        let mut log = self.log.lock().unwrap();
+        if log.length >= self.capacity {
+            match self.policy {
+                Backpressure::Reject => {
+                    self.rejected.fetch_add(1, Ordering::Relaxed);
+                    return Err(LogFull(value));
+                }
+                Backpressure::EvictOldest => {
+                    log.pop();
+                    self.evicted.fetch_add(1, Ordering::Relaxed);
+                }
+                Backpressure::Block => {
+                    log = self
+                        .not_full
+                        .wait_while(log, |log| log.length >= self.capacity)
+                        .unwrap();
+                }
+            }
+        }
+        log.append(value);
+        Ok(())
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        let value = self.log.lock().unwrap().pop();
+        if value.is_some() {
+            self.not_full.notify_one();
+        }
+        value
+    }
+
+    pub fn length(&self) -> u64 {
+        self.log.lock().unwrap().length
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    /// Entries dropped to make room under `Backpressure::EvictOldest`.
+    pub fn evicted(&self) -> u64 {
+        self.evicted.load(Ordering::Relaxed)
+    }
+
+    /// Appends turned away under `Backpressure::Reject`.
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+}
+
+fn main() {
+    println!("Hara Hara Mahadev!!! Bounded Log");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn bounded_transaction_log_reject() {
+        let log = BoundedTransactionLog::new_empty(2, Backpressure::Reject);
+        assert_eq!(log.append("Siva".to_owned()), Ok(()));
+        assert_eq!(log.append("Krishna".to_owned()), Ok(()));
+        assert_eq!(
+            log.append("Shambho".to_owned()),
+            Err(LogFull("Shambho".to_owned()))
+        );
+        assert_eq!(log.length(), 2);
+        assert_eq!(log.rejected(), 1);
+        assert_eq!(log.evicted(), 0);
+        assert_eq!(log.pop(), Some("Siva".to_owned()));
+        assert_eq!(log.append("Shambho".to_owned()), Ok(()));
+        assert_eq!(log.pop(), Some("Krishna".to_owned()));
+        assert_eq!(log.pop(), Some("Shambho".to_owned()));
+        assert_eq!(log.pop(), None);
+    }
+
+    #[test]
+    fn bounded_transaction_log_evict_oldest() {
+        let log = BoundedTransactionLog::new_empty(3, Backpressure::EvictOldest);
+        for i in 0..10u64 {
+            assert_eq!(log.append(i), Ok(()));
+        }
+        assert_eq!(log.length(), 3);
+        assert_eq!(log.capacity(), 3);
+        assert_eq!(log.evicted(), 7);
+        assert_eq!(log.rejected(), 0);
+        assert_eq!(log.pop(), Some(7));
+        assert_eq!(log.pop(), Some(8));
+        assert_eq!(log.pop(), Some(9));
+        assert_eq!(log.pop(), None);
+    }
+
+    #[test]
+    fn bounded_transaction_log_block() {
+        let log = Arc::new(BoundedTransactionLog::new_empty(2, Backpressure::Block));
+        let producer = {
+            let log = log.clone();
+            std::thread::spawn(move || {
+                for i in 0..100u64 {
+                    log.append(i).unwrap();
+                    assert!(log.length() <= 2);
+                }
+            })
+        };
+        let mut popped = Vec::new();
+        while popped.len() < 100 {
+            match log.pop() {
+                Some(value) => popped.push(value),
+                None => std::thread::yield_now(),
+            }
+        }
+        producer.join().unwrap();
+        assert_eq!(popped, (0..100).collect::<Vec<_>>());
+        assert_eq!(log.length(), 0);
+        assert_eq!(log.evicted() + log.rejected(), 0);
+    }
+}
@@ -0,0 +1,374 @@
+use futures_core::Stream;
+use std::{
+    future::Future,
+    pin::Pin,
+    ptr::{self, NonNull},
+    sync::{
+        atomic::{AtomicBool, AtomicPtr, AtomicU64, Ordering},
+        Mutex, PoisonError,
+    },
+    task::{Context, Poll, Waker},
+};
+
+struct ConcurrentNode<T> {
+    // `None` only in the dummy node that `head` points to
+    value: Option<T>,
+    next: AtomicPtr<ConcurrentNode<T>>,
+}
+
+impl<T> ConcurrentNode<T> {
+    fn new(value: Option<T>) -> NonNull<ConcurrentNode<T>> {
+        let node = Box::new(ConcurrentNode {
+            value,
+            next: AtomicPtr::new(ptr::null_mut()),
+        });
+        // SAFETY: `Box::into_raw` never returns a null pointer
+        unsafe { NonNull::new_unchecked(Box::into_raw(node)) }
+    }
+}
+
+/// The entry a closed `ConcurrentTransactionLog` turned away.
+#[derive(Debug, PartialEq, Eq)]
+pub struct LogClosed<T>(pub T);
+
+/// A transaction log many threads can append to and pop from at once.
+///
+/// This is the two-lock queue by Michael and Scott: `head` always points to
+/// a dummy node, so producers only ever take the tail lock and consumers
+/// only ever take the head lock. Async consumers can wait for entries with
+/// `pop_wait` or `stream`, which work with any executor.
+pub struct ConcurrentTransactionLog<T> {
+    head: Mutex<NonNull<ConcurrentNode<T>>>,
+    tail: Mutex<NonNull<ConcurrentNode<T>>>,
+    length: AtomicU64,
+    // only changed while holding the tail lock, so no append can slip in
+    // after a consumer has seen the log closed and empty
+    closed: AtomicBool,
+    wakers: Mutex<Vec<Waker>>,
+}
+
+// SAFETY: nodes are only reached through the locks and values are moved in
+// and out whole, so sharing the log is fine as long as `T` can be sent
+unsafe impl<T: Send> Send for ConcurrentTransactionLog<T> {}
+unsafe impl<T: Send> Sync for ConcurrentTransactionLog<T> {}
+
+impl<T> ConcurrentTransactionLog<T> {
+    pub fn new_empty() -> Self {
+        let dummy = ConcurrentNode::new(None);
+        Self {
+            head: Mutex::new(dummy),
+            tail: Mutex::new(dummy),
+            length: AtomicU64::new(0),
+            closed: AtomicBool::new(false),
+            wakers: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn append(&self, value: T) -> Result<(), LogClosed<T>> {
+        let mut tail = self.tail.lock().unwrap();
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(LogClosed(value));
+        }
+        let new = ConcurrentNode::new(Some(value));
+        // counted before it is visible so `length` never drops below zero
+        self.length.fetch_add(1, Ordering::Relaxed);
+        // SAFETY: the tail node can't be freed while its `next` is null
+        unsafe { tail.as_ref() }
+            .next
+            .store(new.as_ptr(), Ordering::Release);
+        *tail = new;
+        drop(tail);
+        self.wake_all();
+        Ok(())
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        let mut head = self.head.lock().unwrap();
+        // SAFETY: the dummy node is only freed by whoever holds the head lock
+        let next = unsafe { head.as_ref() }.next.load(Ordering::Acquire);
+        let next = NonNull::new(next)?;
+        let dummy = std::mem::replace(&mut *head, next);
+        // SAFETY: `next` becomes the new dummy, producers only touch its
+        // `next` field and nobody else reads its value
+        let value = unsafe { (*next.as_ptr()).value.take() };
+        drop(head);
+        // SAFETY: the old dummy has a successor, so no producer holds it
+        drop(unsafe { Box::from_raw(dummy.as_ptr()) });
+        self.length.fetch_sub(1, Ordering::Relaxed);
+        value
+    }
+
+    /// Waits for the next entry. Resolves to `None` once the log is closed
+    /// and every entry appended before that has been popped.
+    pub fn pop_wait(&self) -> PopWait<'_, T> {
+        PopWait { log: self }
+    }
+
+    /// The entries of the log as a `Stream` that ends when the log is
+    /// closed and drained.
+    pub fn stream(&self) -> LogStream<'_, T> {
+        LogStream { log: self }
+    }
+
+    /// Stops accepting appends and wakes every waiting consumer.
+    pub fn close(&self) {
+        let tail = self.tail.lock().unwrap();
+        self.closed.store(true, Ordering::SeqCst);
+        drop(tail);
+        self.wake_all();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    pub fn length(&self) -> u64 {
+        self.length.load(Ordering::Relaxed)
+    }
+
+    fn poll_pop(&self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        if let Some(value) = self.pop() {
+            return Poll::Ready(Some(value));
+        }
+        {
+            let mut wakers = self.wakers.lock().unwrap();
+            if !wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+                wakers.push(cx.waker().clone());
+            }
+        }
+        // check again in case an append or close happened before the waker
+        // was registered
+        let closed = self.is_closed();
+        match self.pop() {
+            Some(value) => Poll::Ready(Some(value)),
+            None if closed => Poll::Ready(None),
+            None => Poll::Pending,
+        }
+    }
+
+    fn wake_all(&self) {
+        let wakers = std::mem::take(&mut *self.wakers.lock().unwrap());
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+/// Future returned by `ConcurrentTransactionLog::pop_wait`.
+pub struct PopWait<'a, T> {
+    log: &'a ConcurrentTransactionLog<T>,
+}
+
+impl<T> Future for PopWait<'_, T> {
+    type Output = Option<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.log.poll_pop(cx)
+    }
+}
+
+/// Stream returned by `ConcurrentTransactionLog::stream`.
+pub struct LogStream<'a, T> {
+    log: &'a ConcurrentTransactionLog<T>,
+}
+
+impl<T> Stream for LogStream<'_, T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.log.poll_pop(cx)
+    }
+}
+
+impl<T> Drop for ConcurrentTransactionLog<T> {
+    fn drop(&mut self) {
+        let head = self.head.get_mut().unwrap_or_else(PoisonError::into_inner);
+        let mut current = head.as_ptr();
+        while !current.is_null() {
+            // SAFETY: `&mut self` means no other thread can reach the nodes
+            let node = unsafe { Box::from_raw(current) };
+            current = node.next.load(Ordering::Relaxed);
+        }
+    }
+}
+
+fn main() {
+    println!("Hara Hara Mahadev!!! Concurrent Log");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{atomic::AtomicUsize, Arc};
+    use std::time::Duration;
+
+    #[test]
+    fn concurrent_transaction_log_append_pop() {
+        let log = ConcurrentTransactionLog::new_empty();
+        assert_eq!(log.pop(), None);
+        log.append("Siva".to_owned()).unwrap();
+        log.append("Krishna".to_owned()).unwrap();
+        assert_eq!(log.length(), 2);
+        assert_eq!(log.pop(), Some("Siva".to_owned()));
+        log.append("Shambho".to_owned()).unwrap();
+        assert_eq!(log.pop(), Some("Krishna".to_owned()));
+        assert_eq!(log.pop(), Some("Shambho".to_owned()));
+        assert_eq!(log.pop(), None);
+        assert_eq!(log.length(), 0);
+        // entries left behind are freed with the log
+        log.append("Shankara".to_owned()).unwrap();
+    }
+
+    #[test]
+    fn concurrent_transaction_log_stress() {
+        const PRODUCERS: u64 = 4;
+        const CONSUMERS: usize = 3;
+        const PER_PRODUCER: u64 = 20_000;
+        const TOTAL: usize = (PRODUCERS * PER_PRODUCER) as usize;
+
+        let log = Arc::new(ConcurrentTransactionLog::new_empty());
+        let received = Arc::new(AtomicUsize::new(0));
+        let producers: Vec<_> = (0..PRODUCERS)
+            .map(|p| {
+                let log = log.clone();
+                std::thread::spawn(move || {
+                    for i in 0..PER_PRODUCER {
+                        log.append(p * PER_PRODUCER + i).unwrap();
+                    }
+                })
+            })
+            .collect();
+        let consumers: Vec<_> = (0..CONSUMERS)
+            .map(|_| {
+                let log = log.clone();
+                let received = received.clone();
+                std::thread::spawn(move || {
+                    let mut popped = Vec::new();
+                    while received.load(Ordering::Relaxed) < TOTAL {
+                        match log.pop() {
+                            Some(value) => {
+                                popped.push(value);
+                                received.fetch_add(1, Ordering::Relaxed);
+                            }
+                            None => std::thread::yield_now(),
+                        }
+                    }
+                    popped
+                })
+            })
+            .collect();
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        let mut all = Vec::with_capacity(TOTAL);
+        for consumer in consumers {
+            let popped = consumer.join().unwrap();
+            // each consumer sees every producer's entries in append order
+            for p in 0..PRODUCERS {
+                let own = popped.iter().filter(|v| **v / PER_PRODUCER == p);
+                assert!(own.clone().zip(own.skip(1)).all(|(a, b)| a < b));
+            }
+            all.extend(popped);
+        }
+        all.sort_unstable();
+        assert_eq!(all, (0..PRODUCERS * PER_PRODUCER).collect::<Vec<_>>());
+        assert_eq!(log.pop(), None);
+        assert_eq!(log.length(), 0);
+    }
+
+    // a minimal executor, so the tests don't pick a runtime either
+    fn block_on<F: Future>(future: F) -> F::Output {
+        struct Unpark(std::thread::Thread);
+
+        impl std::task::Wake for Unpark {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        let waker = Waker::from(Arc::new(Unpark(std::thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = std::pin::pin!(future);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => std::thread::park(),
+            }
+        }
+    }
+
+    fn next<S: Stream + Unpin>(stream: &mut S) -> impl Future<Output = Option<S::Item>> + '_ {
+        std::future::poll_fn(move |cx| Pin::new(&mut *stream).poll_next(cx))
+    }
+
+    #[test]
+    fn concurrent_transaction_log_pop_wait() {
+        let log = Arc::new(ConcurrentTransactionLog::new_empty());
+        log.append(1u64).unwrap();
+        assert_eq!(block_on(log.pop_wait()), Some(1));
+
+        let consumer = {
+            let log = log.clone();
+            std::thread::spawn(move || block_on(log.pop_wait()))
+        };
+        std::thread::sleep(Duration::from_millis(20));
+        log.append(2).unwrap();
+        assert_eq!(consumer.join().unwrap(), Some(2));
+        assert_eq!(log.length(), 0);
+    }
+
+    #[test]
+    fn concurrent_transaction_log_close() {
+        let log = Arc::new(ConcurrentTransactionLog::new_empty());
+        let consumers: Vec<_> = (0..3)
+            .map(|_| {
+                let log = log.clone();
+                std::thread::spawn(move || block_on(log.pop_wait()))
+            })
+            .collect();
+        std::thread::sleep(Duration::from_millis(20));
+        log.close();
+        for consumer in consumers {
+            assert_eq!(consumer.join().unwrap(), None);
+        }
+        assert!(log.is_closed());
+        assert_eq!(
+            log.append("late".to_owned()),
+            Err(LogClosed("late".to_owned()))
+        );
+
+        // entries appended before closing are still handed out
+        let log = ConcurrentTransactionLog::new_empty();
+        log.append("Siva".to_owned()).unwrap();
+        log.close();
+        assert_eq!(block_on(log.pop_wait()), Some("Siva".to_owned()));
+        assert_eq!(block_on(log.pop_wait()), None);
+    }
+
+    #[test]
+    fn concurrent_transaction_log_stream() {
+        const TOTAL: u64 = 10_000;
+        let log = Arc::new(ConcurrentTransactionLog::new_empty());
+        let consumer = {
+            let log = log.clone();
+            std::thread::spawn(move || {
+                block_on(async {
+                    let mut stream = log.stream();
+                    let mut popped = Vec::new();
+                    while let Some(value) = next(&mut stream).await {
+                        popped.push(value);
+                    }
+                    popped
+                })
+            })
+        };
+        for i in 0..TOTAL {
+            log.append(i).unwrap();
+            if i % 1000 == 0 {
+                std::thread::yield_now();
+            }
+        }
+        log.close();
+        assert_eq!(consumer.join().unwrap(), (0..TOTAL).collect::<Vec<_>>());
+    }
+}
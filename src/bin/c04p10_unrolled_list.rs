@@ -0,0 +1,781 @@
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use std::ptr::{self, NonNull};
+use std::slice;
+
+// Entries per chunk of an `UnrolledTransactionLog`.
+const CHUNK_CAPACITY: usize = 16;
+
+// A node of the list. The entries live inline in the node, and only the
+// first `len` of them are initialized.
+struct Chunk<T> {
+    entries: [MaybeUninit<T>; CHUNK_CAPACITY],
+    len: usize,
+    next: Link<T>,
+    prev: Link<T>,
+}
+
+type Link<T> = Option<NonNull<Chunk<T>>>;
+
+impl<T> Chunk<T> {
+    fn new() -> NonNull<Chunk<T>> {
+        let chunk = Box::new(Chunk {
+            entries: [const { MaybeUninit::uninit() }; CHUNK_CAPACITY],
+            len: 0,
+            next: None,
+            prev: None,
+        });
+        // SAFETY: `Box::into_raw` never returns a null pointer
+        unsafe { NonNull::new_unchecked(Box::into_raw(chunk)) }
+    }
+
+    // A pointer to entry `at` that doesn't go through a reference to the
+    // whole chunk, so the mutable iterator can hand out entries one by one.
+    //
+    // SAFETY: `chunk` must be alive and `at` at most `CHUNK_CAPACITY`
+    unsafe fn entry(chunk: NonNull<Chunk<T>>, at: usize) -> *mut T {
+        unsafe {
+            ptr::addr_of_mut!((*chunk.as_ptr()).entries)
+                .cast::<T>()
+                .add(at)
+        }
+    }
+
+    fn entries(&self) -> &[T] {
+        // SAFETY: the first `len` entries are initialized
+        unsafe { slice::from_raw_parts(self.entries.as_ptr().cast(), self.len) }
+    }
+
+    fn entries_mut(&mut self) -> &mut [T] {
+        // SAFETY: the first `len` entries are initialized
+        unsafe { slice::from_raw_parts_mut(self.entries.as_mut_ptr().cast(), self.len) }
+    }
+
+    // Puts `value` at `at`, shifting the entries after it up by one.
+    fn insert(&mut self, at: usize, value: T) {
+        assert!(self.len < CHUNK_CAPACITY && at <= self.len);
+        // SAFETY: there is room for one more entry, and the shifted entries
+        // stay inside the array
+        unsafe {
+            let slot = self.entries.as_mut_ptr().add(at);
+            ptr::copy(slot, slot.add(1), self.len - at);
+            slot.write(MaybeUninit::new(value));
+        }
+        self.len += 1;
+    }
+
+    // Takes out the entry at `at`, shifting the entries after it down by one.
+    fn remove(&mut self, at: usize) -> T {
+        assert!(at < self.len);
+        // SAFETY: entry `at` is initialized, and once it is read out its slot
+        // is overwritten by the entries after it
+        let value = unsafe {
+            let slot = self.entries.as_mut_ptr().add(at);
+            let value = slot.read().assume_init();
+            ptr::copy(slot.add(1), slot, self.len - at - 1);
+            value
+        };
+        self.len -= 1;
+        value
+    }
+
+    // Moves the entries from `at` on to the end of `other`.
+    fn move_tail(&mut self, at: usize, other: &mut Chunk<T>) {
+        let count = self.len - at;
+        assert!(other.len + count <= CHUNK_CAPACITY);
+        // SAFETY: the moved entries are initialized and no longer counted in
+        // `self`, and `other` has room for them
+        unsafe {
+            ptr::copy_nonoverlapping(
+                self.entries.as_ptr().add(at),
+                other.entries.as_mut_ptr().add(other.len),
+                count,
+            );
+        }
+        self.len = at;
+        other.len += count;
+    }
+}
+
+impl<T> Drop for Chunk<T> {
+    fn drop(&mut self) {
+        // SAFETY: only the initialized entries are dropped, once each
+        unsafe { ptr::drop_in_place(self.entries_mut()) }
+    }
+}
+
+/// Borrowing iterator over an `UnrolledTransactionLog`. It walks in from
+/// both ends, one chunk at a time, and stops once they meet.
+pub struct ListIterator<'a, T> {
+    head: Link<T>,
+    tail: Link<T>,
+    // the next entry in `head` and one past the last entry left in `tail`
+    front: usize,
+    back: usize,
+    length: u64,
+    _list: PhantomData<&'a Chunk<T>>,
+}
+
+impl<'a, T> Iterator for ListIterator<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.length == 0 {
+            return None;
+        }
+        let mut head = self.head?;
+        // SAFETY: the list is borrowed for 'a, chunks are never empty and
+        // `length` keeps the iterator from walking past the other end
+        unsafe {
+            if self.front == (*head.as_ptr()).len {
+                head = (*head.as_ptr()).next?;
+                self.head = Some(head);
+                self.front = 0;
+            }
+            let value = &*Chunk::entry(head, self.front);
+            self.front += 1;
+            self.length -= 1;
+            Some(value)
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.length as usize, Some(self.length as usize))
+    }
+}
+
+impl<T> DoubleEndedIterator for ListIterator<'_, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.length == 0 {
+            return None;
+        }
+        let mut tail = self.tail?;
+        // SAFETY: as in `next`
+        unsafe {
+            if self.back == 0 {
+                tail = (*tail.as_ptr()).prev?;
+                self.tail = Some(tail);
+                self.back = (*tail.as_ptr()).len;
+            }
+            self.back -= 1;
+            self.length -= 1;
+            Some(&*Chunk::entry(tail, self.back))
+        }
+    }
+}
+
+impl<T> ExactSizeIterator for ListIterator<'_, T> {}
+
+/// Mutably borrowing iterator over an `UnrolledTransactionLog`.
+pub struct ListIteratorMut<'a, T> {
+    head: Link<T>,
+    tail: Link<T>,
+    front: usize,
+    back: usize,
+    length: u64,
+    _list: PhantomData<&'a mut Chunk<T>>,
+}
+
+impl<'a, T> Iterator for ListIteratorMut<'a, T> {
+    type Item = &'a mut T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.length == 0 {
+            return None;
+        }
+        let mut head = self.head?;
+        // SAFETY: the list is mutably borrowed for 'a, `length` makes sure no
+        // entry is handed out twice, and chunks are only read through raw
+        // pointers so the entries already handed out stay valid
+        unsafe {
+            if self.front == (*head.as_ptr()).len {
+                head = (*head.as_ptr()).next?;
+                self.head = Some(head);
+                self.front = 0;
+            }
+            let value = &mut *Chunk::entry(head, self.front);
+            self.front += 1;
+            self.length -= 1;
+            Some(value)
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.length as usize, Some(self.length as usize))
+    }
+}
+
+impl<T> DoubleEndedIterator for ListIteratorMut<'_, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.length == 0 {
+            return None;
+        }
+        let mut tail = self.tail?;
+        // SAFETY: as in `next`
+        unsafe {
+            if self.back == 0 {
+                tail = (*tail.as_ptr()).prev?;
+                self.tail = Some(tail);
+                self.back = (*tail.as_ptr()).len;
+            }
+            self.back -= 1;
+            self.length -= 1;
+            Some(&mut *Chunk::entry(tail, self.back))
+        }
+    }
+}
+
+impl<T> ExactSizeIterator for ListIteratorMut<'_, T> {}
+
+pub struct IntoIter<T> {
+    list: UnrolledTransactionLog<T>,
+}
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.list.pop()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.list.length as usize, Some(self.list.length as usize))
+    }
+}
+
+impl<T> DoubleEndedIterator for IntoIter<T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.list.pop_back()
+    }
+}
+
+impl<T> ExactSizeIterator for IntoIter<T> {}
+
+/// An unrolled linked list: a doubly linked list of small arrays.
+///
+/// Each chunk holds up to `CHUNK_CAPACITY` entries inline, next to each
+/// other, so a traversal touches one node per chunk rather than one per
+/// entry. A full chunk is split in half to make room for an insert, and a
+/// chunk that drops below half full after a remove takes in its successor if
+/// both fit.
+pub struct UnrolledTransactionLog<T> {
+    head: Link<T>,
+    tail: Link<T>,
+    chunks: u64,
+    pub length: u64,
+    _owns: PhantomData<Box<Chunk<T>>>,
+}
+
+impl<T> UnrolledTransactionLog<T> {
+    pub fn new_empty() -> Self {
+        Self {
+            head: None,
+            tail: None,
+            chunks: 0,
+            length: 0,
+            _owns: PhantomData,
+        }
+    }
+
+    pub fn append(&mut self, value: T) {
+        let tail = match self.tail {
+            // SAFETY: `tail` is owned by the list
+            Some(tail) if unsafe { tail.as_ref() }.len < CHUNK_CAPACITY => tail,
+            _ => {
+                let chunk = Chunk::new();
+                // SAFETY: a new chunk goes after the current tail
+                unsafe { self.link_after(self.tail, chunk) };
+                chunk
+            }
+        };
+        // SAFETY: as above, and nothing else borrows the chunk
+        let tail = unsafe { &mut *tail.as_ptr() };
+        tail.insert(tail.len, value);
+        self.length += 1;
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        (self.length > 0).then(|| self.remove(0))
+    }
+
+    pub fn push_front(&mut self, value: T) {
+        self.insert(0, value);
+    }
+
+    pub fn pop_back(&mut self) -> Option<T> {
+        let last = self.length.checked_sub(1)?;
+        Some(self.remove(last))
+    }
+
+    pub fn peek_front(&self) -> Option<&T> {
+        // SAFETY: `head` is owned by the list, which is borrowed
+        self.head
+            .and_then(|head| unsafe { &*head.as_ptr() }.entries().first())
+    }
+
+    pub fn peek_back(&self) -> Option<&T> {
+        // SAFETY: `tail` is owned by the list, which is borrowed
+        self.tail
+            .and_then(|tail| unsafe { &*tail.as_ptr() }.entries().last())
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::new_empty();
+    }
+
+    pub fn get(&self, index: u64) -> Option<&T> {
+        let (chunk, at) = self.locate(index)?;
+        // SAFETY: `chunk` is owned by the list, which is borrowed
+        unsafe { &*chunk.as_ptr() }.entries().get(at)
+    }
+
+    pub fn get_mut(&mut self, index: u64) -> Option<&mut T> {
+        let (chunk, at) = self.locate(index)?;
+        // SAFETY: `chunk` is owned by the list, which is mutably borrowed
+        unsafe { &mut *chunk.as_ptr() }.entries_mut().get_mut(at)
+    }
+
+    /// Inserts `value` so that it ends up at `index`, shifting everything
+    /// after it towards the back.
+    ///
+    /// Panics if `index > length`.
+    pub fn insert(&mut self, index: u64, value: T) {
+        assert!(
+            index <= self.length,
+            "cannot insert at {} of {}",
+            index,
+            self.length
+        );
+        let Some((mut chunk, mut at)) = self.locate(index) else {
+            self.append(value);
+            return;
+        };
+        // SAFETY: `chunk` is owned by the list, and the new chunk goes right
+        // after it
+        unsafe {
+            if chunk.as_ref().len == CHUNK_CAPACITY {
+                let back = Chunk::new();
+                (*chunk.as_ptr()).move_tail(CHUNK_CAPACITY / 2, &mut *back.as_ptr());
+                self.link_after(Some(chunk), back);
+                if at > CHUNK_CAPACITY / 2 {
+                    chunk = back;
+                    at -= CHUNK_CAPACITY / 2;
+                }
+            }
+            (*chunk.as_ptr()).insert(at, value);
+        }
+        self.length += 1;
+    }
+
+    /// Removes and returns the entry at `index`, shifting everything after
+    /// it towards the front.
+    ///
+    /// Panics if `index >= length`, like `insert` and `Vec::remove`.
+    pub fn remove(&mut self, index: u64) -> T {
+        let Some((chunk, at)) = self.locate(index) else {
+            panic!("cannot remove at {} of {}", index, self.length);
+        };
+        // SAFETY: `chunk` and its successor are owned by the list, and
+        // neither is borrowed elsewhere
+        let value = unsafe {
+            let current = &mut *chunk.as_ptr();
+            let value = current.remove(at);
+            if current.len == 0 {
+                self.unlink(chunk);
+            } else if let Some(next) = current.next {
+                let next_len = next.as_ref().len;
+                if current.len < CHUNK_CAPACITY / 2 && current.len + next_len <= CHUNK_CAPACITY {
+                    (*next.as_ptr()).move_tail(0, current);
+                    self.unlink(next);
+                }
+            }
+            value
+        };
+        self.length -= 1;
+        value
+    }
+
+    pub fn iter(&self) -> ListIterator<'_, T> {
+        ListIterator {
+            head: self.head,
+            tail: self.tail,
+            front: 0,
+            // SAFETY: `tail` is owned by the list, which is borrowed
+            back: self.tail.map_or(0, |tail| unsafe { tail.as_ref() }.len),
+            length: self.length,
+            _list: PhantomData,
+        }
+    }
+
+    pub fn iter_mut(&mut self) -> ListIteratorMut<'_, T> {
+        ListIteratorMut {
+            head: self.head,
+            tail: self.tail,
+            front: 0,
+            // SAFETY: `tail` is owned by the list, which is mutably borrowed
+            back: self.tail.map_or(0, |tail| unsafe { tail.as_ref() }.len),
+            length: self.length,
+            _list: PhantomData,
+        }
+    }
+
+    // Finds the chunk holding `index` and the position inside it, walking in
+    // from whichever end is closer.
+    fn locate(&self, index: u64) -> Option<(NonNull<Chunk<T>>, usize)> {
+        if index >= self.length {
+            return None;
+        }
+        // SAFETY: every chunk reached from `head` or `tail` is owned by the
+        // list, and `length` is the sum of their lengths
+        unsafe {
+            if index < self.length / 2 {
+                let mut chunk = self.head?;
+                let mut start = 0;
+                loop {
+                    let len = chunk.as_ref().len as u64;
+                    if index < start + len {
+                        return Some((chunk, (index - start) as usize));
+                    }
+                    start += len;
+                    chunk = chunk.as_ref().next?;
+                }
+            } else {
+                let mut chunk = self.tail?;
+                let mut end = self.length;
+                loop {
+                    let start = end - chunk.as_ref().len as u64;
+                    if index >= start {
+                        return Some((chunk, (index - start) as usize));
+                    }
+                    end = start;
+                    chunk = chunk.as_ref().prev?;
+                }
+            }
+        }
+    }
+
+    // Links `chunk` in after `prev`, or at the head if `prev` is `None`.
+    //
+    // SAFETY: `chunk` must be a fresh chunk and `prev` owned by the list
+    unsafe fn link_after(&mut self, prev: Link<T>, chunk: NonNull<Chunk<T>>) {
+        let next = match prev {
+            Some(prev) => unsafe { (*prev.as_ptr()).next.replace(chunk) },
+            None => self.head.replace(chunk),
+        };
+        unsafe {
+            (*chunk.as_ptr()).prev = prev;
+            (*chunk.as_ptr()).next = next;
+        }
+        match next {
+            Some(next) => unsafe { (*next.as_ptr()).prev = Some(chunk) },
+            None => self.tail = Some(chunk),
+        }
+        self.chunks += 1;
+    }
+
+    // Unlinks `chunk` and frees it along with the entries it still holds.
+    //
+    // SAFETY: `chunk` must be owned by the list and not borrowed
+    unsafe fn unlink(&mut self, chunk: NonNull<Chunk<T>>) {
+        let chunk = unsafe { Box::from_raw(chunk.as_ptr()) };
+        match chunk.prev {
+            Some(prev) => unsafe { (*prev.as_ptr()).next = chunk.next },
+            None => self.head = chunk.next,
+        }
+        match chunk.next {
+            Some(next) => unsafe { (*next.as_ptr()).prev = chunk.prev },
+            None => self.tail = chunk.prev,
+        }
+        self.chunks -= 1;
+    }
+}
+
+impl<T> Drop for UnrolledTransactionLog<T> {
+    fn drop(&mut self) {
+        let mut current = self.head.take();
+        self.tail = None;
+        while let Some(chunk) = current {
+            // SAFETY: every chunk is owned by the list and visited only once
+            let chunk = unsafe { Box::from_raw(chunk.as_ptr()) };
+            current = chunk.next;
+        }
+        self.chunks = 0;
+        self.length = 0;
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for UnrolledTransactionLog<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<T> IntoIterator for UnrolledTransactionLog<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter { list: self }
+    }
+}
+
+impl<'a, T> IntoIterator for &'a UnrolledTransactionLog<T> {
+    type Item = &'a T;
+    type IntoIter = ListIterator<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a mut UnrolledTransactionLog<T> {
+    type Item = &'a mut T;
+    type IntoIter = ListIteratorMut<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+fn main() {
+    println!("Hara Hara Mahadev!!! Unrolled Linked List");
+}
+
+// the list the iteration benchmark compares against
+#[cfg(test)]
+#[allow(dead_code)]
+#[path = "c04p02_doubly_linked_list/better_transaction_log.rs"]
+mod better_transaction_log;
+
+#[cfg(test)]
+mod tests {
+    use super::better_transaction_log::BetterTransactionLog;
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::cell::Cell;
+    use std::hint::black_box;
+    use std::time::{Duration, Instant};
+
+    // The generator for the randomised tests. The seed is printed, and a
+    // failing run is replayed by setting `TEST_SEED` to it.
+    fn seeded_rng() -> StdRng {
+        let seed = std::env::var("TEST_SEED")
+            .ok()
+            .and_then(|seed| seed.parse().ok())
+            .unwrap_or_else(rand::random);
+        println!("TEST_SEED={}", seed);
+        StdRng::seed_from_u64(seed)
+    }
+
+    // checks the entries, the links between chunks, and that no chunk is
+    // empty or over capacity
+    fn assert_unrolled(list: &UnrolledTransactionLog<u64>, expected: &[u64]) {
+        assert_eq!(list.iter().copied().collect::<Vec<_>>(), expected);
+        let mut back: Vec<u64> = list.iter().rev().copied().collect();
+        back.reverse();
+        assert_eq!(back, expected);
+        assert_eq!(list.iter().len(), expected.len());
+        assert_eq!(list.length, expected.len() as u64);
+        let mut chunks = 0;
+        let mut prev = None;
+        let mut current = list.head;
+        while let Some(chunk) = current {
+            let chunk = unsafe { chunk.as_ref() };
+            assert_eq!(chunk.prev, prev);
+            assert!(chunk.len > 0 && chunk.len <= CHUNK_CAPACITY);
+            chunks += 1;
+            prev = current;
+            current = chunk.next;
+        }
+        assert_eq!(list.tail, prev);
+        assert_eq!(list.chunks, chunks);
+        assert_eq!(list.peek_front(), expected.first());
+        assert_eq!(list.peek_back(), expected.last());
+    }
+
+    #[test]
+    fn unrolled_transaction_log_append_pop() {
+        let mut list = UnrolledTransactionLog::new_empty();
+        assert_eq!(list.pop(), None);
+        assert_eq!(list.pop_back(), None);
+        for i in 0..40 {
+            list.append(i);
+        }
+        assert_eq!(list.chunks, 3);
+        list.push_front(100);
+        assert_eq!(list.pop(), Some(100));
+        assert_eq!(list.pop(), Some(0));
+        assert_eq!(list.pop_back(), Some(39));
+        let expected: Vec<u64> = (1..39).collect();
+        assert_unrolled(&list, &expected);
+        for value in &mut list {
+            *value *= 2;
+        }
+        assert_eq!(list.get(3), Some(&8));
+        let mut iter = list.iter_mut();
+        *iter.next_back().unwrap() += 1;
+        *iter.next().unwrap() += 1;
+        assert_eq!(iter.len(), 36);
+        assert_eq!(list.peek_front(), Some(&3));
+        assert_eq!(list.into_iter().next_back(), Some(77));
+    }
+
+    #[test]
+    fn unrolled_transaction_log_splits_and_merges() {
+        let mut list = UnrolledTransactionLog::new_empty();
+        for i in 0..CHUNK_CAPACITY as u64 {
+            list.append(i);
+        }
+        assert_eq!(list.chunks, 1);
+        list.insert(3, 100);
+        assert_eq!(list.chunks, 2);
+        let mut expected: Vec<u64> = (0..CHUNK_CAPACITY as u64).collect();
+        expected.insert(3, 100);
+        assert_unrolled(&list, &expected);
+        while list.length > 4 {
+            list.remove(1);
+            expected.remove(1);
+        }
+        assert_eq!(list.chunks, 1);
+        assert_unrolled(&list, &expected);
+        assert_eq!(format!("{:?}", list), format!("{:?}", expected));
+    }
+
+    #[test]
+    #[should_panic]
+    fn unrolled_transaction_log_remove_out_of_bounds() {
+        let mut list = UnrolledTransactionLog::new_empty();
+        list.append(1);
+        list.remove(1);
+    }
+
+    #[test]
+    fn unrolled_transaction_log_against_vec() {
+        let mut rng = seeded_rng();
+        for _ in 0..50 {
+            let mut list = UnrolledTransactionLog::new_empty();
+            let mut oracle: Vec<u64> = Vec::new();
+            for step in 0..500 {
+                let len = oracle.len() as u64;
+                match rng.gen_range(0..8) {
+                    0..=2 => {
+                        let at = rng.gen_range(0..=len);
+                        list.insert(at, step);
+                        oracle.insert(at as usize, step);
+                    }
+                    3 | 4 => {
+                        if let Some(at) = (len > 0).then(|| rng.gen_range(0..len)) {
+                            assert_eq!(list.remove(at), oracle.remove(at as usize));
+                        }
+                    }
+                    5 => {
+                        list.append(step);
+                        oracle.push(step);
+                    }
+                    6 => assert_eq!(list.pop_back(), oracle.pop()),
+                    _ => {
+                        let at = rng.gen_range(0..=len);
+                        assert_eq!(list.get(at), oracle.get(at as usize));
+                        if let Some(value) = list.get_mut(at) {
+                            *value += 1;
+                            oracle[at as usize] += 1;
+                        }
+                    }
+                }
+            }
+            assert_unrolled(&list, &oracle);
+        }
+    }
+
+    thread_local! {
+        static LIVE_VALUES: Cell<usize> = const { Cell::new(0) };
+    }
+
+    // Counts the values of the current test thread that haven't been dropped.
+    struct Counted;
+
+    impl Counted {
+        fn new() -> Self {
+            LIVE_VALUES.with(|live| live.set(live.get() + 1));
+            Counted
+        }
+    }
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            LIVE_VALUES.with(|live| live.set(live.get() - 1));
+        }
+    }
+
+    fn live_values() -> usize {
+        LIVE_VALUES.with(Cell::get)
+    }
+
+    #[test]
+    fn unrolled_transaction_log_drops_every_entry_once() {
+        let mut list = UnrolledTransactionLog::new_empty();
+        for _ in 0..100 {
+            list.append(Counted::new());
+        }
+        list.insert(50, Counted::new());
+        assert_eq!(live_values(), 101);
+        // emptying chunks and merging them moves entries without dropping
+        for _ in 0..60 {
+            list.remove(20);
+        }
+        assert_eq!(live_values(), 41);
+        list.clear();
+        assert_eq!(live_values(), 0);
+
+        let mut list = UnrolledTransactionLog::new_empty();
+        for _ in 0..100 {
+            list.push_front(Counted::new());
+        }
+        let mut iter = list.into_iter();
+        iter.next();
+        iter.next_back();
+        assert_eq!(live_values(), 98);
+        drop(iter);
+        assert_eq!(live_values(), 0);
+    }
+
+    fn time_iteration<'a>(iter: impl Iterator<Item = &'a u64>) -> Duration {
+        let start = Instant::now();
+        let sum: u64 = iter.map(|value| black_box(*value)).sum();
+        black_box(sum);
+        start.elapsed()
+    }
+
+    // cargo test --release --bin c04p10_unrolled_list -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn unrolled_transaction_log_iteration() {
+        const N: u64 = 5_000_000;
+        let vec: Vec<u64> = (0..N).collect();
+        let mut list = BetterTransactionLog::new_empty();
+        let mut unrolled = UnrolledTransactionLog::new_empty();
+        // filled one after the other so neither list's allocations are
+        // interleaved with the other's
+        for i in 0..N {
+            list.append(i);
+        }
+        for i in 0..N {
+            unrolled.append(i);
+        }
+        let mops = |d: Duration| N as f64 / d.as_secs_f64() / 1e6;
+        for round in 1..=3 {
+            let vec = time_iteration(vec.iter());
+            let list = time_iteration(list.iter());
+            let unrolled = time_iteration(unrolled.iter());
+            println!(
+                "round {}: Vec {:.1} Mops/s, list {:.1} Mops/s, unrolled {:.1} Mops/s, {:.2}x over list",
+                round,
+                mops(vec),
+                mops(list),
+                mops(unrolled),
+                list.as_secs_f64() / unrolled.as_secs_f64()
+            );
+        }
+    }
+}